@@ -0,0 +1,205 @@
+﻿use runtime_error::{ RuntimeError, RuntimeErrorKind };
+use expressions::call;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+use storage::Storage;
+use span::Span;
+use value::{ Value, missing_key };
+use map::Map;
+
+type NativeFunc = fn(Vec<Value>, &mut Storage) -> Result<Value, RuntimeError>;
+
+// native functions every script starts with
+pub const FUNCTIONS: [(&str, NativeFunc); 12] = [
+    ("print", print),
+    ("len", len),
+    ("push", push),
+    ("pop", pop),
+    ("map", map),
+    ("filter", filter),
+    ("reduce", reduce),
+    ("sort", sort),
+    ("keys", keys),
+    ("values", values),
+    ("has", has),
+    ("remove", remove)
+];
+
+fn expect_arity(name: &str, args: &[Value], min: usize, max: usize) -> Result<(), RuntimeError> {
+    if args.len() >= min && args.len() <= max {
+        return Ok(());
+    }
+
+    let expected = if min == max { min.to_string() } else { format!("{} to {}", min, max) };
+    let plural = if max == 1 { "argument" } else { "arguments" };
+    let message = format!("`{}` expects {} {}, found {}", name, expected, plural, args.len());
+
+    Err(RuntimeError::new(RuntimeErrorKind::ArityError, message))
+}
+
+fn expect_list(name: &str, value: &Value) -> Result<Rc<RefCell<Vec<Value>>>, RuntimeError> {
+    match value {
+        Value::List(items) => Ok(items.clone()),
+        value => {
+            let message = format!("`{}` expects a list, found {}", name, value.type_name());
+            Err(RuntimeError::new(RuntimeErrorKind::TypeError, message))
+        }
+    }
+}
+
+fn expect_map(name: &str, value: &Value) -> Result<Rc<RefCell<Map>>, RuntimeError> {
+    match value {
+        Value::Map(entries) => Ok(entries.clone()),
+        value => {
+            let message = format!("`{}` expects a map, found {}", name, value.type_name());
+            Err(RuntimeError::new(RuntimeErrorKind::TypeError, message))
+        }
+    }
+}
+
+// callbacks have no call site of their own
+fn call_back(storage: &mut Storage, func: &Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    call(storage, func.clone(), args, "<anonymous>", Span::default())
+}
+
+fn print(args: Vec<Value>, _: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("print", &args, 1, 1)?;
+
+    println!("{}", args[0]);
+
+    Ok(Value::Null)
+}
+
+fn len(args: Vec<Value>, _: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("len", &args, 1, 1)?;
+
+    match &args[0] {
+        Value::List(items) => Ok(Value::Int(items.borrow().len() as i64)),
+        Value::String(value) => Ok(Value::Int(value.chars().count() as i64)),
+        Value::Map(entries) => Ok(Value::Int(entries.borrow().len() as i64)),
+        value => {
+            let message = format!("`len` expects a list, map or string, found {}", value.type_name());
+            Err(RuntimeError::new(RuntimeErrorKind::TypeError, message))
+        }
+    }
+}
+
+fn push(args: Vec<Value>, _: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("push", &args, 2, 2)?;
+
+    expect_list("push", &args[0])?.borrow_mut().push(args[1].clone());
+
+    Ok(Value::Null)
+}
+
+fn pop(args: Vec<Value>, _: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("pop", &args, 1, 1)?;
+
+    expect_list("pop", &args[0])?.borrow_mut().pop().ok_or_else(|| {
+        RuntimeError::new(RuntimeErrorKind::IndexError, "cannot pop from an empty list".to_string())
+    })
+}
+
+fn map(args: Vec<Value>, storage: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("map", &args, 2, 2)?;
+
+    // the callback may change the list, so it walks a copy
+    let items = expect_list("map", &args[0])?.borrow().clone();
+    let mut result = Vec::new();
+
+    for item in items {
+        result.push(call_back(storage, &args[1], vec![item])?);
+    }
+
+    Ok(Value::List(Rc::new(RefCell::new(result))))
+}
+
+fn filter(args: Vec<Value>, storage: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("filter", &args, 2, 2)?;
+
+    let items = expect_list("filter", &args[0])?.borrow().clone();
+    let mut result = Vec::new();
+
+    for item in items {
+        if call_back(storage, &args[1], vec![item.clone()])?.is_truthy() {
+            result.push(item);
+        }
+    }
+
+    Ok(Value::List(Rc::new(RefCell::new(result))))
+}
+
+// `reduce(xs f)` starts from the first item, `reduce(xs f initial)` from `initial`
+fn reduce(args: Vec<Value>, storage: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("reduce", &args, 2, 3)?;
+
+    let mut items = expect_list("reduce", &args[0])?.borrow().clone().into_iter();
+
+    let mut result = match args.get(2) {
+        Some(initial) => initial.clone(),
+        None => items.next().ok_or_else(|| {
+            RuntimeError::new(RuntimeErrorKind::TypeError, "cannot reduce an empty list without an initial value".to_string())
+        })?
+    };
+
+    for item in items {
+        result = call_back(storage, &args[1], vec![result, item])?;
+    }
+
+    Ok(result)
+}
+
+// sorts in place and returns the same list
+fn sort(args: Vec<Value>, _: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("sort", &args, 1, 1)?;
+
+    let list = expect_list("sort", &args[0])?;
+    let mut items = list.borrow().clone();
+    let mut error = None;
+
+    items.sort_by(|item1, item2| match item1.compare(item2) {
+        Ok(ord) => ord.unwrap_or(Ordering::Equal),
+        Err(err) => {
+            error.get_or_insert(err);
+            Ordering::Equal
+        }
+    });
+
+    if let Some(error) = error {
+        return Err(error);
+    }
+
+    *list.borrow_mut() = items;
+
+    Ok(args[0].clone())
+}
+
+fn keys(args: Vec<Value>, _: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("keys", &args, 1, 1)?;
+
+    let keys = expect_map("keys", &args[0])?.borrow().keys();
+
+    Ok(Value::List(Rc::new(RefCell::new(keys))))
+}
+
+fn values(args: Vec<Value>, _: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("values", &args, 1, 1)?;
+
+    let values = expect_map("values", &args[0])?.borrow().values();
+
+    Ok(Value::List(Rc::new(RefCell::new(values))))
+}
+
+fn has(args: Vec<Value>, _: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("has", &args, 2, 2)?;
+
+    Ok(Value::Bool(expect_map("has", &args[0])?.borrow().get(&args[1]).is_some()))
+}
+
+// removes a key and returns its value
+fn remove(args: Vec<Value>, _: &mut Storage) -> Result<Value, RuntimeError> {
+    expect_arity("remove", &args, 2, 2)?;
+
+    expect_map("remove", &args[0])?.borrow_mut().remove(&args[1]).ok_or_else(|| missing_key(&args[1]))
+}
@@ -0,0 +1,144 @@
+﻿use std::fmt::Formatter;
+use std::fmt::Display;
+use std::fmt::Error;
+use tokens::{ Token, TokenType };
+use span::Span;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiagnosticKind {
+    UnexpectedCharacter,
+    MalformedNumber,
+    UnexpectedToken,
+    InvalidParameter,
+    MisplacedKeyword,
+    UnterminatedString,
+    UnterminatedComment,
+    InvalidPipeTarget,
+    InvalidEscape,
+    UnusedLambda
+}
+
+// kind, expected and found are meant for hosts that inspect diagnostics
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub span: Span,
+    pub expected: Vec<TokenType>,
+    pub found: Option<TokenType>,
+    pub message: String,
+    pub help: Option<String>
+}
+
+impl Diagnostic {
+    pub fn new(kind: DiagnosticKind, span: Span, message: String) -> Diagnostic {
+        Diagnostic {
+            kind,
+            span,
+            expected: Vec::new(),
+            found: None,
+            message,
+            help: None
+        }
+    }
+
+    pub fn with_help(mut self, help: &str) -> Diagnostic {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    pub fn unexpected_token(expected: Vec<TokenType>, found: &Token) -> Diagnostic {
+        let expected_names: Vec<String> = expected.iter().map(describe).collect();
+        let message = match expected_names.len() {
+            0 => format!("unexpected {}", describe(&found.token_type)),
+            _ => format!("expected {}, found {}", expected_names.join(" or "), describe(&found.token_type))
+        };
+
+        Diagnostic {
+            kind: DiagnosticKind::UnexpectedToken,
+            span: found.span,
+            expected,
+            found: Some(found.token_type.clone()),
+            message,
+            help: None
+        }
+    }
+
+    pub fn expected_expression(found: &Token) -> Diagnostic {
+        Diagnostic {
+            kind: DiagnosticKind::UnexpectedToken,
+            span: found.span,
+            expected: Vec::new(),
+            found: Some(found.token_type.clone()),
+            message: format!("expected expression, found {}", describe(&found.token_type)),
+            help: None
+        }
+    }
+
+    pub fn render(&self, file_name: &str, source: &str, color: bool) -> String {
+        render("error", &self.message, self.span, self.help.as_ref(), file_name, source, color)
+    }
+}
+
+// renders an error rustc-style with the offending source line and a caret under the span
+pub fn render(title: &str, message: &str, span: Span, help: Option<&String>, file_name: &str, source: &str, color: bool) -> String {
+    let paint = |code: &str, text: &str| -> String {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    };
+
+    let line_number = span.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+    let source_line = source.lines().nth(span.line.max(1) - 1).unwrap_or("");
+    let source_line = source_line.trim_end_matches('\r');
+
+    // keep tabs so the caret lines up with the source line
+    let padding: String = source_line
+        .chars()
+        .take(span.column.max(1) - 1)
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+    let line_rest = source_line.chars().count().saturating_sub(span.column.max(1) - 1);
+    let span_len = source.get(span.start..span.end).map_or(0, |text| text.chars().count());
+    let underline = "^".repeat(span_len.min(line_rest).max(1));
+
+    let mut output = String::new();
+
+    output += &format!("{}: {}\n", paint("1;31", title), paint("1", message));
+    output += &format!("{}{} {}:{}\n", gutter, paint("1;34", "-->"), file_name, span);
+    output += &format!("{} {}\n", gutter, paint("1;34", "|"));
+    output += &format!("{} {} {}\n", paint("1;34", &line_number), paint("1;34", "|"), source_line);
+    output += &format!("{} {} {}{}\n", gutter, paint("1;34", "|"), padding, paint("1;31", &underline));
+
+    if let Some(help) = help {
+        output += &format!("{} {} {}\n", gutter, paint("1;34", "="), paint("1", &format!("help: {}", help)));
+    }
+
+    output
+}
+
+// token classes are written as words, everything else as quoted source text
+fn describe(token_type: &TokenType) -> String {
+    match token_type {
+        TokenType::Number |
+        TokenType::Integer |
+        TokenType::HexNumber |
+        TokenType::OctNumber |
+        TokenType::BinNumber |
+        TokenType::VariableKey |
+        TokenType::StringValue |
+        TokenType::StringPart |
+        TokenType::Newline |
+        TokenType::EOF => token_type.to_string(),
+        _ => format!("`{}`", token_type)
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
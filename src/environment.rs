@@ -4,20 +4,31 @@ use std::rc::Rc;
 
 pub type Environment = Rc<RefCell<EnvironmentData>>;
 
-#[derive(Debug, Clone, Default)]
+// slots of dropped environments, shared by every environment of a storage
+pub type Released = Rc<RefCell<Vec<usize>>>;
+
+#[derive(Debug, Default)]
 pub struct EnvironmentData {
     variables: HashMap<String, usize>,
-    pub outer: Option<Environment>
+    pub outer: Option<Environment>,
+    released: Released
 }
 
 impl EnvironmentData {
     pub fn new(outer: Option<Environment>) -> EnvironmentData {
+        let released = match outer {
+            Some(ref outer) => Rc::clone(&outer.borrow().released),
+            None => Released::default()
+        };
+
         EnvironmentData {
             variables: HashMap::new(),
-            outer: outer
+            outer,
+            released
         }
     }
 
+    #[allow(dead_code)]
     pub fn top(env: &Environment) -> Environment {
         match env.borrow().outer {
             Some(ref outer) => EnvironmentData::top(outer),
@@ -29,27 +40,46 @@ impl EnvironmentData {
         self.variables.insert(key, var);
     }
 
-    pub fn env_of(&self, key: String) -> Option<Environment> {
-        if self.variables.contains_key(&key) {
-            Some(Rc::new(RefCell::new(self.clone())))
-        } else {
-            match self.outer {
-                Some(ref outer) => outer.borrow().env_of(key),
-                None => None,
+    // unbinds the nearest `key`, returns the slot it used
+    pub fn remove(&mut self, key: &str) -> Option<usize> {
+        match self.variables.remove(key) {
+            Some(var) => Some(var),
+            None => match self.outer {
+                Some(ref outer) => outer.borrow_mut().remove(key),
+                None => None
             }
         }
     }
 
+    pub fn slots(&self) -> Vec<usize> {
+        self.variables.values().cloned().collect()
+    }
+
+    // unbinds everything, returns the slots that were used
+    pub fn clear(&mut self) -> Vec<usize> {
+        self.variables.drain().map(|(_, var)| var).collect()
+    }
+
+    pub fn released(&self) -> Released {
+        Rc::clone(&self.released)
+    }
+
     pub fn get(&self, key: String) -> Option<usize> {
         match self.variables.get(&key) {
-            Some(var) => Some(var.clone()),
-            None => {
-                if let Some(env) = self.env_of(key.to_string()) {
-                    env.borrow().get(key)
-                } else {
-                    None
-                }
+            Some(var) => Some(*var),
+            None => match self.outer {
+                Some(ref outer) => outer.borrow().get(key),
+                None => None
             }
         }
     }
 }
+
+impl Drop for EnvironmentData {
+    // hands the slots back to the storage, which frees them on its next collect
+    fn drop(&mut self) {
+        let slots = self.clear();
+
+        self.released.borrow_mut().extend(slots);
+    }
+}
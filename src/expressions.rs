@@ -1,120 +1,448 @@
-﻿use storage::{ StorageVariable, Storage };
+﻿use runtime_error::{ RuntimeError, RuntimeErrorKind };
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+use storage::{ StorageVariable, Storage };
 use tokens::TokenType;
-use value::Value;
+use span::Span;
+use value::{ Value, check_key };
+use map::Map;
+
+#[derive(Clone, Debug)]
+pub struct Parameter {
+    pub name: String,
+    pub default: Option<Box<Expression>>, // evaluated at call time in the function scope
+    pub rest: bool // collects the remaining arguments into a list
+}
+
+// non-local exits unwinding through nested expressions
+pub enum Signal {
+    Error(RuntimeError),
+    Return(Value, Span), // caught by the nearest function call
+    Break(Span), // caught by the nearest loop
+    Continue(Span) // caught by the nearest loop
+}
+
+impl Signal {
+    // turns a signal that escaped everything that could catch it into an error
+    fn into_error(self) -> RuntimeError {
+        let (message, span) = match self {
+            Signal::Error(error) => return error,
+            Signal::Return(_, span) => ("`return` outside of a function", span),
+            Signal::Break(span) => ("`break` outside of a loop", span),
+            Signal::Continue(span) => ("`continue` outside of a loop", span)
+        };
+
+        RuntimeError::new(RuntimeErrorKind::InvalidControlFlow, message.to_string()).at(span)
+    }
+}
+
+impl From<RuntimeError> for Signal {
+    fn from(error: RuntimeError) -> Signal {
+        Signal::Error(error)
+    }
+}
 
 #[derive(Clone, Debug)]
 pub enum Expression {
-    Null,
-    NumberValue(f64),
-    StringValue(String),
-    Binary(TokenType, Box<Expression>, Box<Expression>),
-    Unary(TokenType, Box<Expression>),
-    GetVariable(String),
-    SetVariable(String, Box<Expression>),
-    Block(Vec<Box<Expression>>),
-    Function(Box<Expression>, Vec<String>),
-    CallFunc(String, Vec<Box<Expression>>),
-    Return(Box<Expression>)
+    Null(Span),
+    IntValue(i64, Span),
+    NumberValue(f64, Span),
+    StringValue(String, Span),
+    Interpolation(Vec<Expression>, Span), // text and embedded expressions in order
+    ListValue(Vec<Expression>, Span),
+    MapValue(Vec<(Expression, Expression)>, Span), // keys and values in order
+    BoolValue(bool, Span),
+    Binary(TokenType, Box<Expression>, Box<Expression>, Span),
+    Logical(TokenType, Box<Expression>, Box<Expression>, Span), // short-circuiting `and` and `or`
+    Unary(TokenType, Box<Expression>, Span),
+    GetVariable(String, Span),
+    SetVariable(String, Box<Expression>, Span),
+    Index(Box<Expression>, Box<Expression>, Span), // target, index
+    Slice(Box<Expression>, Option<Box<Expression>>, Option<Box<Expression>>, Span), // target, start, end
+    SetIndex(Box<Expression>, Box<Expression>, Box<Expression>, Span), // target, index, value
+    Remove(Box<Expression>, Span), // a variable or an index to unbind
+    Block(Vec<Expression>, Span),
+    Function(Box<Expression>, Vec<Parameter>, Span),
+    Call(Box<Expression>, Vec<Expression>, Span),
+    Return(Option<Box<Expression>>, Span),
+    If(Box<Expression>, Box<Expression>, Option<Box<Expression>>, Span), // condition, then, else
+    While(Box<Expression>, Box<Expression>, Span), // condition, body
+    For(String, Box<Expression>, Box<Expression>, Span), // variable, iterable, body
+    Break(Span),
+    Continue(Span),
+    Error(Span) // placeholder for source that failed to parse
 }
 
 impl Expression {
-    pub fn eval(&self, storage: &mut Storage) -> Value {
+    pub fn span(&self) -> Span {
         match self {
-            Expression::Null => Value::Null,
-            Expression::NumberValue(value) => Value::Number(*value),
-            Expression::StringValue(value) => Value::String(value.to_string()),
-            Expression::Binary(op_type, left_expr, right_expr) => {
-                let left = left_expr.eval(storage);
-                let right = right_expr.eval(storage);
-
-                match op_type {
+            Expression::Null(span) => *span,
+            Expression::IntValue(_, span) => *span,
+            Expression::NumberValue(_, span) => *span,
+            Expression::StringValue(_, span) => *span,
+            Expression::Interpolation(_, span) => *span,
+            Expression::ListValue(_, span) => *span,
+            Expression::MapValue(_, span) => *span,
+            Expression::BoolValue(_, span) => *span,
+            Expression::Binary(_, _, _, span) => *span,
+            Expression::Logical(_, _, _, span) => *span,
+            Expression::Unary(_, _, span) => *span,
+            Expression::GetVariable(_, span) => *span,
+            Expression::SetVariable(_, _, span) => *span,
+            Expression::Index(_, _, span) => *span,
+            Expression::Slice(_, _, _, span) => *span,
+            Expression::SetIndex(_, _, _, span) => *span,
+            Expression::Remove(_, span) => *span,
+            Expression::Block(_, span) => *span,
+            Expression::Function(_, _, span) => *span,
+            Expression::Call(_, _, span) => *span,
+            Expression::Return(_, span) => *span,
+            Expression::If(_, _, _, span) => *span,
+            Expression::While(_, _, span) => *span,
+            Expression::For(_, _, _, span) => *span,
+            Expression::Break(span) => *span,
+            Expression::Continue(span) => *span,
+            Expression::Error(span) => *span
+        }
+    }
+
+    // name used for the callee in messages and stack traces
+    fn callee_name(&self) -> String {
+        match self {
+            Expression::GetVariable(key, _) => key.to_string(),
+            _ => "<anonymous>".to_string()
+        }
+    }
+
+    pub fn eval(&self, storage: &mut Storage) -> Result<Value, RuntimeError> {
+        self.exec(storage).map_err(Signal::into_error)
+    }
+
+    fn exec(&self, storage: &mut Storage) -> Result<Value, Signal> {
+        match self {
+            Expression::Null(_) => Ok(Value::Null),
+            Expression::IntValue(value, _) => Ok(Value::Int(*value)),
+            Expression::NumberValue(value, _) => Ok(Value::Number(*value)),
+            Expression::StringValue(value, _) => Ok(Value::String(value.to_string())),
+            Expression::BoolValue(value, _) => Ok(Value::Bool(*value)),
+            Expression::Interpolation(parts, _) => {
+                let mut string = String::new();
+
+                for part in parts {
+                    string += &part.exec(storage)?.to_string();
+                }
+
+                Ok(Value::String(string))
+            },
+            Expression::ListValue(exprs, _) => {
+                let mut items = Vec::new();
+
+                for expr in exprs {
+                    items.push(expr.exec(storage)?);
+                }
+
+                Ok(Value::List(Rc::new(RefCell::new(items))))
+            },
+            Expression::Binary(op_type, left_expr, right_expr, span) => {
+                let left = left_expr.exec(storage)?;
+                let right = right_expr.exec(storage)?;
+
+                let result = match op_type {
                     TokenType::Add => left + right,
                     TokenType::Substract => left - right,
                     TokenType::Multiply => left * right,
                     TokenType::Divide => left / right,
-                    _ => Value::Null
-                }
+                    TokenType::Modulo => left % right,
+                    TokenType::Power => left.pow(right),
+                    TokenType::IntDivide => left.int_div(right),
+                    TokenType::BitAnd => left & right,
+                    TokenType::BitOr => left | right,
+                    TokenType::BitXor => left ^ right,
+                    TokenType::ShiftLeft => left << right,
+                    TokenType::ShiftRight => left >> right,
+                    TokenType::Equal => Ok(Value::Bool(left.equals(&right))),
+                    TokenType::NotEqual => Ok(Value::Bool(!left.equals(&right))),
+                    TokenType::Less |
+                    TokenType::LessEqual |
+                    TokenType::Greater |
+                    TokenType::GreaterEqual => left.compare(&right).map(|ord| Value::Bool(ordering_matches(op_type, ord))),
+                    _ => Ok(Value::Null)
+                };
+
+                Ok(result.map_err(|error| error.at(*span))?)
             },
-            Expression::Unary(op_type, expr) => {
-                match op_type {
-                    TokenType::Add => expr.eval(storage),
-                    TokenType::Substract => -expr.eval(storage),
-                    _ => Value::Null
+            Expression::Logical(op_type, left_expr, right_expr, _) => {
+                let left = left_expr.exec(storage)?.is_truthy();
+
+                // the right operand is only evaluated when it decides the result
+                if (*op_type == TokenType::And && !left) || (*op_type == TokenType::Or && left) {
+                    return Ok(Value::Bool(left));
                 }
+
+                Ok(Value::Bool(right_expr.exec(storage)?.is_truthy()))
             },
-            Expression::GetVariable(key) => {
-                storage.get(StorageVariable::User(key.to_string()))
+            Expression::Unary(op_type, expr, span) => {
+                let value = expr.exec(storage)?;
+
+                let result = match op_type {
+                    TokenType::Add => Ok(value),
+                    TokenType::Substract => -value,
+                    TokenType::BitNot => !value,
+                    TokenType::Not => Ok(Value::Bool(!value.is_truthy())),
+                    _ => Ok(Value::Null)
+                };
+
+                Ok(result.map_err(|error| error.at(*span))?)
             },
-            Expression::SetVariable(key, expr) => {
-                let value = expr.eval(storage);
+            Expression::GetVariable(key, span) => {
+                let value = storage.get(StorageVariable::User(key.to_string())).map_err(|error| {
+                    RuntimeError::new(RuntimeErrorKind::UndefinedVariable, error.to_string()).at(*span)
+                })?;
+
+                Ok(value)
+            },
+            Expression::SetVariable(key, expr, _) => {
+                let value = expr.exec(storage)?;
 
                 match storage.store(StorageVariable::User(key.to_string()), value.clone()) {
-                    Ok(_) => storage.get(StorageVariable::User(key.to_string())),
+                    Ok(_) => { },
                     Err(_) => {
                         storage.new_var(key.to_string());
-                        let _ = storage.store(StorageVariable::User(key.to_string()), value);
-                        storage.get(StorageVariable::User(key.to_string()))
+                        let _ = storage.store(StorageVariable::User(key.to_string()), value.clone());
                     }
                 }
+
+                Ok(value)
+            },
+            Expression::MapValue(pairs, _) => {
+                let mut entries = Map::new();
+
+                for (key_expr, value_expr) in pairs {
+                    let key = key_expr.exec(storage)?;
+                    check_key(&key).map_err(|error| error.at(key_expr.span()))?;
+
+                    entries.insert(key, value_expr.exec(storage)?);
+                }
+
+                Ok(Value::Map(Rc::new(RefCell::new(entries))))
+            },
+            Expression::Index(target, index, span) => {
+                let target = target.exec(storage)?;
+                let index = index.exec(storage)?;
+
+                Ok(target.index(&index).map_err(|error| error.at(*span))?)
+            },
+            Expression::Slice(target, start, end, span) => {
+                let target = target.exec(storage)?;
+                let start = match start {
+                    Some(start) => Some(start.exec(storage)?),
+                    None => None
+                };
+                let end = match end {
+                    Some(end) => Some(end.exec(storage)?),
+                    None => None
+                };
+
+                Ok(target.slice(start.as_ref(), end.as_ref()).map_err(|error| error.at(*span))?)
             },
-            Expression::Block(exprs) => {
-                let mut result = Value::Null;
+            Expression::SetIndex(target, index, expr, span) => {
+                let target = target.exec(storage)?;
+                let index = index.exec(storage)?;
+                let value = expr.exec(storage)?;
+
+                target.set_index(&index, value.clone()).map_err(|error| error.at(*span))?;
+
+                Ok(value)
+            },
+            Expression::Remove(target, span) => {
+                let result = match target.as_ref() {
+                    Expression::GetVariable(key, _) => storage.remove(key.to_string()).map_err(|error| {
+                        RuntimeError::new(RuntimeErrorKind::UndefinedVariable, error.to_string())
+                    }),
+                    Expression::Index(target, index, _) => {
+                        let target = target.exec(storage)?;
+                        let index = index.exec(storage)?;
+
+                        target.remove_index(&index)
+                    },
+                    _ => Ok(Value::Null)
+                };
+
+                Ok(result.map_err(|error| error.at(*span))?)
+            },
+            Expression::Block(exprs, _) => {
+                let mut result = Ok(Value::Null);
 
                 storage.scope_start();
 
                 for expr in exprs {
-                    /*match Box::leak(expr.clone()) {
-                        Expression::Return(_) => {
-                            return expr.eval(storage);
-                        },
-                        _ => { expr.eval(storage); }
-                    }*/
-
-                    result = expr.eval(storage);
+                    result = expr.exec(storage);
+
+                    if result.is_err() {
+                        break;
+                    }
                 }
 
                 storage.scope_end();
 
                 result
             },
-            Expression::Function(ref expr, args) => {
-                Value::Function(expr.clone(), args.to_vec())
+            Expression::Function(ref expr, args, _) => {
+                Ok(Value::Function(expr.clone(), args.to_vec(), storage.env()))
             },
-            Expression::CallFunc(key, args) => {
-                let func = storage.get(StorageVariable::User(key.to_string()));
-                let mut result = Value::Null;
+            Expression::Call(callee, args, span) => {
+                let func = callee.exec(storage)?;
+                let mut values = Vec::new();
 
-                match func {
-                    Value::Function(expr, arg_names) => {
-                        storage.scope_start();
+                for arg in args {
+                    values.push(arg.exec(storage)?);
+                }
 
-                        for i in 0..arg_names.len() {
-                            let key = arg_names[i].to_string();
-                            let value = args[i].eval(storage);
-                            match storage.store(StorageVariable::User(key.to_string()), value.clone()) {
-                                Ok(_) => { },
-                                Err(_) => {
-                                    storage.new_var(key.to_string());
-                                    let _ = storage.store(StorageVariable::User(key.to_string()), value);
-                                }
-                            }
-                        }
+                Ok(call(storage, func, values, &callee.callee_name(), *span)?)
+            },
+            Expression::Return(expr, span) => {
+                let value = match expr {
+                    Some(expr) => expr.exec(storage)?,
+                    None => Value::Null
+                };
 
-                        result = expr.eval(storage).clone();
+                Err(Signal::Return(value, *span))
+            },
+            Expression::If(condition, then_branch, else_branch, _) => {
+                if condition.exec(storage)?.is_truthy() {
+                    then_branch.exec(storage)
+                } else {
+                    match else_branch {
+                        Some(else_branch) => else_branch.exec(storage),
+                        None => Ok(Value::Null)
+                    }
+                }
+            },
+            Expression::While(condition, body, _) => {
+                while condition.exec(storage)?.is_truthy() {
+                    match body.exec(storage) {
+                        Ok(_) | Err(Signal::Continue(_)) => { },
+                        Err(Signal::Break(_)) => break,
+                        Err(signal) => return Err(signal)
+                    }
+                }
 
-                        storage.scope_end();
-                    },
-                    Value::NativeFunc(f) => {
-                        f(args.to_vec(), storage);
-                    },
-                    _ => { panic!("Attempt to call not a function"); }
+                Ok(Value::Null)
+            },
+            Expression::For(key, iterable, body, _) => {
+                let items = iterable.exec(storage)?.items().map_err(|error| error.at(iterable.span()))?;
+
+                // each pass gets its own scope, so closures made in the body keep their item
+                for item in items {
+                    storage.scope_start();
+                    storage.declare(key.to_string(), item);
+                    let pass = body.exec(storage);
+                    storage.scope_end();
+
+                    match pass {
+                        Ok(_) | Err(Signal::Continue(_)) => { },
+                        Err(Signal::Break(_)) => break,
+                        Err(signal) => return Err(signal)
+                    }
                 }
 
-                result
+                Ok(Value::Null)
             },
-            Expression::Return(expr) => {
-                expr.eval(storage)
+            Expression::Break(span) => Err(Signal::Break(*span)),
+            Expression::Continue(span) => Err(Signal::Continue(*span)),
+            Expression::Error(span) => {
+                Err(RuntimeError::new(RuntimeErrorKind::SyntaxError, "cannot evaluate code that failed to parse".to_string()).at(*span).into())
             }
         }
     }
 }
+
+fn ordering_matches(op_type: &TokenType, ord: Option<Ordering>) -> bool {
+    match (op_type, ord) {
+        (TokenType::Less, Some(ord)) => ord == Ordering::Less,
+        (TokenType::LessEqual, Some(ord)) => ord != Ordering::Greater,
+        (TokenType::Greater, Some(ord)) => ord == Ordering::Greater,
+        (TokenType::GreaterEqual, Some(ord)) => ord != Ordering::Less,
+        (_, _) => false
+    }
+}
+
+fn arity_error(name: &str, params: &[Parameter], given: usize) -> RuntimeError {
+    let required = params.iter().filter(|param| param.default.is_none() && !param.rest).count();
+    let positional = params.iter().filter(|param| !param.rest).count();
+
+    let expected = if params.iter().any(|param| param.rest) {
+        format!("at least {}", required)
+    } else if required == positional {
+        required.to_string()
+    } else {
+        format!("{} to {}", required, positional)
+    };
+    let plural = if expected == "1" || expected.ends_with(" 1") { "argument" } else { "arguments" };
+    let message = format!("`{}` expects {} {}, found {}", name, expected, plural, given);
+
+    RuntimeError::new(RuntimeErrorKind::ArityError, message)
+}
+
+// binds call arguments to parameters in the current scope
+fn bind_parameters(storage: &mut Storage, params: &[Parameter], args: Vec<Value>) -> Result<(), RuntimeError> {
+    let mut args = args.into_iter();
+
+    for param in params {
+        let value = if param.rest {
+            Value::List(Rc::new(RefCell::new(args.by_ref().collect())))
+        } else {
+            match (args.next(), &param.default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => default.eval(storage)?,
+                (None, None) => Value::Null
+            }
+        };
+
+        storage.declare(param.name.to_string(), value);
+    }
+
+    Ok(())
+}
+
+pub fn call(storage: &mut Storage, func: Value, args: Vec<Value>, name: &str, span: Span) -> Result<Value, RuntimeError> {
+    match func {
+        Value::Function(expr, params, env) => {
+            let required = params.iter().filter(|param| param.default.is_none() && !param.rest).count();
+            let has_rest = params.iter().any(|param| param.rest);
+
+            if args.len() < required || (!has_rest && args.len() > params.len()) {
+                return Err(arity_error(name, &params, args.len()).at(span));
+            }
+
+            let caller_env = storage.scope_start_in(env);
+
+            let result = match bind_parameters(storage, &params, args) {
+                Ok(_) => match expr.exec(storage) {
+                    Ok(value) | Err(Signal::Return(value, _)) => Ok(value),
+                    Err(signal) => Err(signal.into_error())
+                },
+                Err(error) => Err(error)
+            };
+
+            storage.scope_restore(caller_env);
+
+            result.map_err(|error| error.in_frame(name, span, storage.file()))
+        },
+        Value::NativeFunc(f) => {
+            f(args, storage).map_err(|error| {
+                let error = if error.span == Span::default() { error.at(span) } else { error };
+
+                error.in_frame(name, span, storage.file())
+            })
+        },
+        value => {
+            let message = match name {
+                "<anonymous>" => format!("cannot call a {}", value.type_name()),
+                _ => format!("`{}` is a {}, not a function", name, value.type_name())
+            };
+            Err(RuntimeError::new(RuntimeErrorKind::NotCallable, message).at(span))
+        }
+    }
+}
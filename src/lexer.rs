@@ -1,96 +1,277 @@
 ﻿use std::vec::Vec;
+use diagnostic::{ Diagnostic, DiagnosticKind };
+use span::Span;
 use tokens::Token;
 use tokens::TokenType;
 
+const OPERATORS: &str = "+-*/%&|^~(){}[],:.;";
+const OP_TOKENS: [TokenType; 19] = [
+    TokenType::Add,
+    TokenType::Substract,
+    TokenType::Multiply,
+    TokenType::Divide,
+    TokenType::Modulo,
+    TokenType::BitAnd,
+    TokenType::BitOr,
+    TokenType::BitXor,
+    TokenType::BitNot,
+    TokenType::LeftParen,
+    TokenType::RightParen,
+    TokenType::LeftBrace,
+    TokenType::RightBrace,
+    TokenType::LeftBracket,
+    TokenType::RightBracket,
+    TokenType::Comma,
+    TokenType::Colon,
+    TokenType::Dot,
+    TokenType::Semicolon
+];
+
 pub struct Lexer<'a> {
     pub input: &'a str,
     lenght: usize,
     pub output: Vec<Token>,
-    position: usize
+    pub trivia: Vec<Token>, // doc comments, which the parser never sees
+    pub diagnostics: Vec<Diagnostic>,
+    position: usize, // byte offset of the current character
+    line: usize,
+    column: usize,
+    token_start: Span,
+    groups: Vec<char> // open `(`, `[`, `{` and `${`, newlines only end statements directly inside braces
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Lexer<'a> {
+        // a byte order mark is not part of the first line
+        let start = if input.starts_with('\u{feff}') { '\u{feff}'.len_utf8() } else { 0 };
+
         Lexer {
-            input: input,
+            input,
             lenght: input.len(),
             output: Vec::new(),
-            position: 0
+            trivia: Vec::new(),
+            diagnostics: Vec::new(),
+            position: start,
+            line: 1,
+            column: 1,
+            token_start: Span::new(start, start, 1, 1),
+            groups: Vec::new()
         }
     }
 
     fn peek(&self, relative_position: usize) -> char {
-        let pos = self.position + relative_position;
-
-        if pos >= self.lenght {
+        if self.position >= self.lenght {
             return '\0';
         }
 
-        let chars : Vec<char> = self.input.chars().collect();
-
-        chars[pos]
+        self.input[self.position..].chars().nth(relative_position).unwrap_or('\0')
     }
 
     fn next(&mut self) -> char {
-        self.position += 1;
+        let curr_ch = self.peek(0);
+
+        if self.position < self.lenght {
+            self.position += curr_ch.len_utf8();
+
+            if curr_ch == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
 
         self.peek(0)
     }
 
+    fn start_token(&mut self) {
+        self.token_start = Span::new(self.position, self.position, self.line, self.column);
+    }
+
+    fn token_span(&self) -> Span {
+        Span::new(self.token_start.start, self.position, self.token_start.line, self.token_start.column)
+    }
+
+    fn char_span(&self) -> Span {
+        let end = self.position + self.peek(0).len_utf8().min(self.lenght - self.position);
+
+        Span::new(self.position, end, self.line, self.column)
+    }
+
     fn add_token(&mut self, token_type: TokenType, data: String) {
-        self.output.push(Token::new(token_type, data));
+        let span = self.token_span();
+
+        self.output.push(Token::new(token_type, data, span));
     }
 
-    fn tokenize_number(&mut self) {
-        let mut num = String::new();
+    fn error(&mut self, kind: DiagnosticKind, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic::new(kind, span, message));
+    }
+
+    // reads digits of the given radix, `_` may separate them and is dropped
+    fn read_digits(&mut self, radix: u32, num: &mut String) -> bool {
         let mut curr_ch = self.peek(0);
+        let mut valid = true;
 
         loop {
-            if curr_ch == '.' {
-                if num.find('.') != None {
-                    panic!("number already contains dot");
-                }
-                num.push(curr_ch);
-                curr_ch = self.next();
-            } else if curr_ch.is_digit(10) {
+            if curr_ch.is_digit(radix) {
                 num.push(curr_ch);
-                curr_ch = self.next();
+            } else if curr_ch == '_' && !num.is_empty() && self.peek(1).is_digit(radix) && !num.ends_with('.') {
+                // skipped
+            } else if curr_ch == '_' {
+                let span = self.char_span();
+                self.error(DiagnosticKind::MalformedNumber, span, "`_` must separate digits".to_string());
+                valid = false;
             } else {
                 break;
             }
+
+            curr_ch = self.next();
         }
 
-        if (curr_ch == 'x' || curr_ch == 'X') && num == "0".to_string() {
+        valid
+    }
+
+    fn tokenize_number(&mut self) {
+        let mut num = String::new();
+        let mut valid = true;
+        let mut token_type = TokenType::Integer;
+
+        if self.peek(0) == '0' {
+            let radix = match self.peek(1) {
+                'x' | 'X' => Some((16, TokenType::HexNumber, "hex")),
+                'o' | 'O' => Some((8, TokenType::OctNumber, "octal")),
+                'b' | 'B' => Some((2, TokenType::BinNumber, "binary")),
+                _ => None
+            };
+
+            if let Some((radix, token_type, name)) = radix {
+                self.next();
+                self.next();
+
+                valid = self.read_digits(radix, &mut num);
+
+                if num.is_empty() && valid {
+                    let span = self.token_span();
+                    self.error(DiagnosticKind::MalformedNumber, span, format!("{} number has no digits", name));
+                    valid = false;
+                }
+
+                self.finish_number(token_type, num, valid, name);
+                return;
+            }
+        }
+
+        if self.peek(0) != '.' {
+            valid &= self.read_digits(10, &mut num);
+        }
+
+        if self.peek(0) == '.' && self.peek(1).is_ascii_digit() {
+            token_type = TokenType::Number;
+            num.push('.');
             self.next();
-            self.tokenize_hex_number();
-            return;
+            valid &= self.read_digits(10, &mut num);
+
+            if self.peek(0) == '.' && self.peek(1).is_ascii_digit() {
+                while self.peek(0) == '.' || self.peek(0).is_ascii_digit() {
+                    self.next();
+                }
+
+                let span = self.token_span();
+                self.error(DiagnosticKind::MalformedNumber, span, "number contains more than one dot".to_string());
+                valid = false;
+            }
+        }
+
+        if self.peek(0) == 'e' || self.peek(0) == 'E' {
+            token_type = TokenType::Number;
+            num.push('e');
+
+            if self.next() == '+' || self.peek(0) == '-' {
+                num.push(self.peek(0));
+                self.next();
+            }
+
+            if !self.peek(0).is_ascii_digit() {
+                let span = self.token_span();
+                self.error(DiagnosticKind::MalformedNumber, span, "exponent has no digits".to_string());
+                valid = false;
+            }
+
+            valid &= self.read_digits(10, &mut num);
         }
 
-        self.add_token(TokenType::Number, num);
+        self.finish_number(token_type, num, valid, "decimal");
     }
 
-    fn tokenize_hex_number(&mut self) {
-        let mut num = String::new();
-        let mut curr_ch = self.peek(0);
+    fn finish_number(&mut self, token_type: TokenType, num: String, valid: bool, name: &str) {
+        let curr_ch = self.peek(0);
 
-        while curr_ch.is_digit(16) {
-            num.push(curr_ch);
-            curr_ch = self.next();
+        // `0b12` or `10px` are typos rather than a number followed by a name
+        if curr_ch.is_alphanumeric() || curr_ch == '_' {
+            let span = self.char_span();
+            self.error(DiagnosticKind::MalformedNumber, span, format!("invalid digit `{}` in {} number", curr_ch, name));
+
+            while self.peek(0).is_alphanumeric() || self.peek(0) == '_' {
+                self.next();
+            }
+
+            return;
         }
 
-        self.add_token(TokenType::HexNumber, num);
+        if valid {
+            self.add_token(token_type, num);
+        }
     }
 
     fn tokenize_operator(&mut self, op_type: TokenType) {
-        if op_type == TokenType::Substract && self.next() == '>' {
-            self.add_token(TokenType::ActionPointer, String::new());
+        // two character operators that start with a single character one
+        let long_type = match (&op_type, self.peek(1)) {
+            (TokenType::Substract, '>') => Some(TokenType::ActionPointer),
+            (TokenType::Multiply, '*') => Some(TokenType::Power),
+            (TokenType::BitNot, '/') => Some(TokenType::IntDivide),
+            (_, _) => None
+        };
+
+        if let Some(long_type) = long_type {
+            self.next();
+            self.next();
+            self.add_token(long_type, String::new());
 
             return;
         }
 
+        match op_type {
+            TokenType::LeftParen => self.groups.push('('),
+            TokenType::LeftBracket => self.groups.push('['),
+            TokenType::LeftBrace => self.groups.push('{'),
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => {
+                self.groups.pop();
+            },
+            _ => { }
+        }
+
+        self.next();
         self.add_token(op_type, String::new());
     }
 
+    fn tokenize_newline(&mut self) {
+        let inside_braces = match self.groups.last() {
+            Some(group) => *group == '{',
+            None => true
+        };
+        let repeated = match self.output.last() {
+            Some(token) => token.token_type == TokenType::Newline,
+            None => true
+        };
+
+        self.next();
+
+        if inside_braces && !repeated {
+            self.add_token(TokenType::Newline, String::new());
+        }
+    }
+
     fn tokenize_key_word(&mut self) {
         let mut key_word = String::new();
         let mut curr_ch = self.peek(0);
@@ -103,84 +284,401 @@ impl<'a> Lexer<'a> {
             curr_ch = self.next();
         }
 
-        if key_word == "null".to_string() {
+        if key_word == "null" {
             self.add_token(TokenType::Null, String::new());
-        } else if key_word == "function".to_string() {
+        } else if key_word == "function" {
             self.add_token(TokenType::Func, String::new());
+        } else if key_word == "return" {
+            self.add_token(TokenType::Return, String::new());
+        } else if key_word == "if" {
+            self.add_token(TokenType::If, String::new());
+        } else if key_word == "else" {
+            self.add_token(TokenType::Else, String::new());
+        } else if key_word == "while" {
+            self.add_token(TokenType::While, String::new());
+        } else if key_word == "for" {
+            self.add_token(TokenType::For, String::new());
+        } else if key_word == "in" {
+            self.add_token(TokenType::In, String::new());
+        } else if key_word == "break" {
+            self.add_token(TokenType::Break, String::new());
+        } else if key_word == "continue" {
+            self.add_token(TokenType::Continue, String::new());
+        } else if key_word == "true" {
+            self.add_token(TokenType::True, String::new());
+        } else if key_word == "false" {
+            self.add_token(TokenType::False, String::new());
+        } else if key_word == "and" {
+            self.add_token(TokenType::And, String::new());
+        } else if key_word == "or" {
+            self.add_token(TokenType::Or, String::new());
+        } else if key_word == "not" {
+            self.add_token(TokenType::Not, String::new());
         } else {
             self.add_token(TokenType::VariableKey, key_word);
         }
     }
 
-    fn tokenize_string(&mut self) {
+    // `"..."` stays on one line, `"""..."""` may span lines, raw strings starting with `r` keep backslashes
+    fn tokenize_string(&mut self, raw: bool) {
         let mut string = String::new();
-        let mut curr_ch = self.peek(0);
+        let triple = self.peek(1) == '\"' && self.peek(2) == '\"';
+        let quote_span = self.char_span();
 
-        while curr_ch != '\"' {
-            string.push(curr_ch);
-            curr_ch = self.next();
+        self.next();
+        if triple {
+            self.next();
+            self.next();
+        }
+
+        loop {
+            let curr_ch = self.peek(0);
+
+            if self.position >= self.lenght || (!triple && curr_ch == '\n') {
+                let help = if triple { "add a closing `\"\"\"`" } else { "add a closing `\"`, strings spanning lines use `\"\"\"`" };
+                let diagnostic = Diagnostic::new(DiagnosticKind::UnterminatedString, quote_span, "unterminated string".to_string())
+                    .with_help(help);
+                self.diagnostics.push(diagnostic);
+
+                // the parser still gets the string, so it doesn't report a missing value on top
+                self.add_token(TokenType::StringValue, string);
+
+                return;
+            }
+
+            if curr_ch == '\"' && (!triple || (self.peek(1) == '\"' && self.peek(2) == '\"')) {
+                break;
+            }
+
+            if curr_ch == '$' && self.peek(1) == '{' && !raw {
+                self.add_token(TokenType::StringPart, string);
+                string = String::new();
+
+                if !self.tokenize_interpolation() {
+                    return;
+                }
+
+                continue;
+            }
+
+            if curr_ch == '\\' && !raw {
+                if let Some(ch) = self.tokenize_escape() {
+                    string.push(ch);
+                }
+            } else {
+                string.push(curr_ch);
+                self.next();
+            }
+        }
+
+        self.next();
+        if triple {
+            self.next();
+            self.next();
         }
 
         self.add_token(TokenType::StringValue, string);
+    }
+
+    // tokenizes the expression inside `${...}`, returns false when the file ends inside it
+    fn tokenize_interpolation(&mut self) -> bool {
+        let open_span = self.char_span();
+        let mut depth = 0;
+
+        self.next();
+        self.next();
+        self.groups.push('$');
+
+        loop {
+            let curr_ch = self.peek(0);
+
+            if self.position >= self.lenght {
+                let diagnostic = Diagnostic::new(DiagnosticKind::UnterminatedString, open_span, "unterminated interpolation".to_string())
+                    .with_help("add a closing `}`");
+                self.diagnostics.push(diagnostic);
+
+                return false;
+            }
+
+            if curr_ch == '}' && depth == 0 {
+                break;
+            } else if curr_ch == '{' {
+                depth += 1;
+            } else if curr_ch == '}' {
+                depth -= 1;
+            }
+
+            self.tokenize_token();
+        }
+
+        self.groups.pop();
+        self.start_token();
+        self.next();
+        self.add_token(TokenType::InterpolationEnd, String::new());
+
+        // the rest of the string is the next token
+        self.start_token();
+
+        true
+    }
+
+    // reads an escape sequence starting at the backslash
+    fn tokenize_escape(&mut self) -> Option<char> {
+        let start = self.char_span();
+
+        let ch = match self.next() {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '"' => '"',
+            '\\' => '\\',
+            '$' => '$',
+            'u' if self.peek(1) == '{' => return self.tokenize_unicode_escape(start),
+            // the backslash stays and the string deals with the line break
+            '\n' => return Some('\\'),
+            '\r' if self.peek(1) == '\n' => return Some('\\'),
+            // the string reports the missing end
+            _ if self.position >= self.lenght => return None,
+            curr_ch => {
+                let span = Span::new(start.start, self.position + curr_ch.len_utf8(), start.line, start.column);
+                let diagnostic = Diagnostic::new(DiagnosticKind::InvalidEscape, span, format!("unknown escape sequence `\\{}`", curr_ch.escape_default()))
+                    .with_help("valid escapes are `\\n`, `\\t`, `\\r`, `\\0`, `\\\"`, `\\\\`, `\\$` and `\\u{...}`");
+                self.diagnostics.push(diagnostic);
+                self.next();
+
+                return None;
+            }
+        };
+
+        self.next();
+
+        Some(ch)
+    }
+
+    // `\u{1F600}`, up to six hex digits naming a code point
+    fn tokenize_unicode_escape(&mut self, start: Span) -> Option<char> {
+        let mut code = String::new();
 
         self.next();
+        while self.next().is_ascii_hexdigit() {
+            code.push(self.peek(0));
+        }
+
+        if self.peek(0) != '}' {
+            let span = Span::new(start.start, self.position, start.line, start.column);
+            self.error(DiagnosticKind::InvalidEscape, span, "unicode escape is missing its closing `}`".to_string());
+
+            return None;
+        }
+
+        self.next();
+
+        let span = Span::new(start.start, self.position, start.line, start.column);
+
+        if code.is_empty() {
+            self.error(DiagnosticKind::InvalidEscape, span, "empty unicode escape".to_string());
+            return None;
+        }
+
+        if code.len() > 6 {
+            self.error(DiagnosticKind::InvalidEscape, span, format!("unicode escape `{}` has more than six hex digits", code));
+            return None;
+        }
+
+        let ch = u32::from_str_radix(&code, 16).ok().and_then(std::char::from_u32);
+
+        if ch.is_none() {
+            self.error(DiagnosticKind::InvalidEscape, span, format!("`{}` is not a valid unicode code point", code));
+        }
+
+        ch
+    }
+
+    // `<` starts `<-`, `<=`, `<<` or `<`
+    fn tokenize_less(&mut self) {
+        match self.next() {
+            '<' => {
+                self.next();
+                self.add_token(TokenType::ShiftLeft, String::new());
+            },
+            '-' => {
+                self.next();
+                self.add_token(TokenType::Setter, String::new());
+            },
+            '=' => {
+                self.next();
+                self.add_token(TokenType::LessEqual, String::new());
+            },
+            _ => self.add_token(TokenType::Less, String::new())
+        }
+    }
+
+    // `>` starts `><`, `>=`, `>>` or `>`
+    fn tokenize_greater(&mut self) {
+        match self.next() {
+            '>' => {
+                self.next();
+                self.add_token(TokenType::ShiftRight, String::new());
+            },
+            '<' => {
+                self.next();
+                self.add_token(TokenType::Remover, String::new());
+            },
+            '=' => {
+                self.next();
+                self.add_token(TokenType::GreaterEqual, String::new());
+            },
+            _ => self.add_token(TokenType::Greater, String::new())
+        }
     }
 
-    fn tokenize_setter(&mut self) {
-        if self.next() == '-' {
-            self.add_token(TokenType::Setter, String::new());
+    fn tokenize_equal(&mut self) {
+        if self.next() == '=' {
+            self.next();
 
+            self.add_token(TokenType::Equal, String::new());
+        } else {
+            let span = self.token_span();
+            let message = format!("expected `=` after `=`, found {}", describe_char(self.peek(0)));
+            let diagnostic = Diagnostic::new(DiagnosticKind::UnexpectedCharacter, span, message)
+                .with_help("variables are assigned with `name <- value`, equality is checked with `==`");
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    fn tokenize_not_equal(&mut self) {
+        if self.next() == '=' {
             self.next();
+
+            self.add_token(TokenType::NotEqual, String::new());
         } else {
-            panic!("Expected \'-\', found \'{}\'", self.peek(0));
+            let span = self.token_span();
+            let message = format!("expected `=` after `!`, found {}", describe_char(self.peek(0)));
+            let diagnostic = Diagnostic::new(DiagnosticKind::UnexpectedCharacter, span, message)
+                .with_help("booleans are negated with `not`");
+            self.diagnostics.push(diagnostic);
         }
     }
 
-    fn tokenize_remover(&mut self) {
-        if self.next() == '<' {
-            self.add_token(TokenType::Remover, String::new());
+    // `// text` is skipped, `/// text` is kept as a doc comment
+    fn tokenize_line_comment(&mut self) {
+        let mut text = String::new();
+
+        self.next();
+        self.next();
+
+        let doc = self.peek(0) == '/' && self.peek(1) != '/';
+        if doc {
+            self.next();
+        }
 
+        while self.position < self.lenght && self.peek(0) != '\n' {
+            text.push(self.peek(0));
             self.next();
-        } else {
-            panic!("Expected \'<\', found \'{}\'", self.peek(0));
+        }
+
+        if doc {
+            let span = self.token_span();
+            let text = text.trim_end_matches('\r').to_string();
+
+            self.trivia.push(Token::new(TokenType::DocComment, text, span));
         }
     }
 
-    pub fn tokenize(&mut self) {
-        let op_tokens : [TokenType; 8] = [
-            TokenType::Add,
-            TokenType::Substract,
-            TokenType::Multiply,
-            TokenType::Divide,
-            TokenType::LeftParen,
-            TokenType::RightParen,
-            TokenType::LeftBrace,
-            TokenType::RightBrace
-        ];
+    // `/* */` comments nest, so commenting out code that has comments works
+    fn tokenize_block_comment(&mut self) {
+        let mut open_spans = Vec::new();
 
         while self.position < self.lenght {
-            let curr_ch = self.peek(0);
-
-            if curr_ch == '\"' {
+            if self.peek(0) == '/' && self.peek(1) == '*' {
+                open_spans.push(Span::new(self.position, self.position + 2, self.line, self.column));
                 self.next();
-                self.tokenize_string();
-            } else if curr_ch == '<' {
-                self.tokenize_setter();
-            } else if curr_ch == '>' {
-                self.tokenize_remover();
-            } else if curr_ch.is_digit(10) {
-                self.tokenize_number();
-            } else if curr_ch.is_alphabetic() {
-                self.tokenize_key_word();
-            } else {
-                let ch = "+-*/(){}".find(curr_ch);
+            } else if self.peek(0) == '*' && self.peek(1) == '/' {
+                open_spans.pop();
+                self.next();
+            }
 
-                if ch != None {
-                    self.tokenize_operator(op_tokens[ch.unwrap()].clone());
-                }
+            self.next();
 
-                self.next();
+            if open_spans.is_empty() {
+                return;
+            }
+        }
+
+        // the outermost comment that never closed
+        let diagnostic = Diagnostic::new(DiagnosticKind::UnterminatedComment, open_spans[0], "unterminated block comment".to_string())
+            .with_help("add a closing `*/`");
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn tokenize_token(&mut self) {
+        let curr_ch = self.peek(0);
+
+        self.start_token();
+
+        if curr_ch == '\"' {
+            self.tokenize_string(false);
+        } else if curr_ch == 'r' && self.peek(1) == '\"' {
+            self.next();
+            self.tokenize_string(true);
+        } else if curr_ch == '/' && self.peek(1) == '/' {
+            self.tokenize_line_comment();
+        } else if curr_ch == '/' && self.peek(1) == '*' {
+            self.tokenize_block_comment();
+        } else if curr_ch == '\n' {
+            self.tokenize_newline();
+        } else if curr_ch == '<' {
+            self.tokenize_less();
+        } else if curr_ch == '>' {
+            self.tokenize_greater();
+        } else if curr_ch == '=' {
+            self.tokenize_equal();
+        } else if curr_ch == '!' {
+            self.tokenize_not_equal();
+        } else if curr_ch == '.' && self.peek(1) == '.' && self.peek(2) == '.' {
+            self.next();
+            self.next();
+            self.next();
+            self.add_token(TokenType::Ellipsis, String::new());
+        } else if curr_ch.is_ascii_digit() || (curr_ch == '.' && self.peek(1).is_ascii_digit()) {
+            self.tokenize_number();
+        } else if curr_ch.is_alphabetic() {
+            self.tokenize_key_word();
+        } else {
+            match OPERATORS.find(curr_ch) {
+                Some(ch) => self.tokenize_operator(OP_TOKENS[ch].clone()),
+                None => {
+                    if !curr_ch.is_whitespace() && curr_ch != '\u{feff}' {
+                        let span = self.char_span();
+                        self.error(DiagnosticKind::UnexpectedCharacter, span, format!("unexpected character `{}`", curr_ch));
+                    }
+
+                    self.next();
+                }
             }
         }
     }
+
+    pub fn tokenize(&mut self) -> Result<(), Vec<Diagnostic>> {
+        while self.position < self.lenght {
+            self.tokenize_token();
+        }
+
+        self.start_token();
+        self.add_token(TokenType::EOF, String::new());
+
+        if self.diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(self.diagnostics.clone())
+        }
+    }
+}
+
+fn describe_char(ch: char) -> String {
+    match ch {
+        '\0' => "end of file".to_string(),
+        _ => format!("`{}`", ch.escape_default())
+    }
 }
@@ -50,44 +50,78 @@ fn main() {
 }
 */
 
+mod span;
+mod diagnostic;
+mod runtime_error;
 mod stack;
 mod types;
 mod value;
+mod map;
 mod environment;
 mod storage;
 mod tokens;
 mod lexer;
 mod parser;
 mod expressions;
+mod builtins;
 
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::IsTerminal;
+use std::process;
+use diagnostic::Diagnostic;
 use lexer::Lexer;
 use parser::Parser;
 use storage::Storage;
 
+fn report(file_path: &str, source: &str, diagnostics: Vec<Diagnostic>) -> ! {
+    let color = std::io::stderr().is_terminal();
+
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(file_path, source, color));
+    }
+
+    process::exit(1);
+}
+
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let file_path = args[1].clone();
 
-    let mut f = File::open(file_path).expect("file not found");
+    let mut f = File::open(&file_path).expect("file not found");
 
     let mut contents = String::new();
     f.read_to_string(&mut contents)
         .expect("something went wrong reading the file");
 
     let mut storage = Storage::default();
+    storage.set_file(&file_path);
     storage.init_std();
 
+    let mut diagnostics = Vec::new();
+
+    // the parser still runs on what the lexer made of a broken file, so every error shows at once
     let mut lexer = Lexer::new(&contents);
-    lexer.tokenize();
+    if let Err(errors) = lexer.tokenize() {
+        diagnostics.extend(errors);
+    }
 
     let mut parser = Parser::new(&lexer.output);
-    parser.parse();
+    if let Err(errors) = parser.parse() {
+        diagnostics.extend(errors);
+    }
+
+    if !diagnostics.is_empty() {
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        report(&file_path, &contents, diagnostics);
+    }
 
     for expr in parser.output {
-        expr.eval(&mut storage);
+        if let Err(error) = expr.eval(&mut storage) {
+            eprintln!("{}", error.render(&file_path, &contents, std::io::stderr().is_terminal()));
+            process::exit(1);
+        }
     }
 }
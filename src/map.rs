@@ -0,0 +1,64 @@
+﻿use std::collections::HashMap;
+use value::Value;
+
+// map entries in insertion order, with a hash index from key to position
+#[derive(Default)]
+pub struct Map {
+    entries: Vec<(Value, Value)>,
+    index: HashMap<Value, usize>
+}
+
+impl Map {
+    pub fn new() -> Map {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.index.get(key).map(|position| &self.entries[*position].1)
+    }
+
+    // keys must be hashable, see `Value::is_hashable`
+    pub fn insert(&mut self, key: Value, value: Value) {
+        match self.index.get(&key) {
+            Some(position) => self.entries[*position].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        let position = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(position);
+
+        // entries after the removed one moved back by one
+        for (key, _) in &self.entries[position..] {
+            if let Some(position) = self.index.get_mut(key) {
+                *position -= 1;
+            }
+        }
+
+        Some(value)
+    }
+
+    pub fn entries(&self) -> &[(Value, Value)] {
+        &self.entries
+    }
+
+    pub fn keys(&self) -> Vec<Value> {
+        self.entries.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    pub fn values(&self) -> Vec<Value> {
+        self.entries.iter().map(|(_, value)| value.clone()).collect()
+    }
+}
@@ -1,21 +1,84 @@
-﻿use expressions::Expression;
+﻿use diagnostic::{ Diagnostic, DiagnosticKind };
+use expressions::{ Expression, Parameter };
 use tokens::TokenType;
 use tokens::Token;
+use span::Span;
+
+type ParseResult = Result<Box<Expression>, Diagnostic>;
+
+enum Associativity {
+    Left,
+    Right
+}
+
+// precedence and associativity of every binary operator, higher binds tighter
+fn binary_operator(token_type: &TokenType) -> Option<(u8, Associativity)> {
+    match token_type {
+        TokenType::ActionPointer => Some((0, Associativity::Left)),
+        TokenType::Or => Some((1, Associativity::Left)),
+        TokenType::And => Some((2, Associativity::Left)),
+        TokenType::Equal | TokenType::NotEqual => Some((4, Associativity::Left)),
+        TokenType::Less | TokenType::LessEqual | TokenType::Greater | TokenType::GreaterEqual => Some((5, Associativity::Left)),
+        TokenType::BitOr => Some((6, Associativity::Left)),
+        TokenType::BitXor => Some((7, Associativity::Left)),
+        TokenType::BitAnd => Some((8, Associativity::Left)),
+        TokenType::ShiftLeft | TokenType::ShiftRight => Some((9, Associativity::Left)),
+        TokenType::Add | TokenType::Substract => Some((10, Associativity::Left)),
+        TokenType::Multiply | TokenType::Divide | TokenType::Modulo | TokenType::IntDivide => Some((11, Associativity::Left)),
+        TokenType::Power => Some((13, Associativity::Right)),
+        _ => None
+    }
+}
+
+// `value -> f(b)` is `f(value b)` and `value -> f` is `f(value)`
+fn pipe(value: Expression, target: Box<Expression>) -> ParseResult {
+    let span = value.span().to(target.span());
+
+    match *target {
+        Expression::Call(callee, mut args, _) => {
+            args.insert(0, value);
+            Ok(Box::new(Expression::Call(callee, args, span)))
+        },
+        Expression::GetVariable(_, _) | Expression::Index(_, _, _) | Expression::Function(_, _, _) => {
+            Ok(Box::new(Expression::Call(target, vec![value], span)))
+        },
+        target => {
+            let message = "the right side of `->` must be a function or a call".to_string();
+            Err(Diagnostic::new(DiagnosticKind::InvalidPipeTarget, target.span(), message)
+                .with_help("`value -> f(b)` calls `f(value b)`"))
+        }
+    }
+}
+
+// precedence of every prefix operator, `-2 ** 2` is -4 because `**` binds tighter than `-`
+fn prefix_operator(token_type: &TokenType) -> Option<u8> {
+    match token_type {
+        TokenType::Not => Some(3),
+        TokenType::Substract | TokenType::Add | TokenType::BitNot => Some(12),
+        _ => None
+    }
+}
 
 pub struct Parser<'a> {
-    pub input: &'a Vec<Token>,
-    pub output: Vec<Box<Expression>>,
+    pub input: &'a [Token],
+    pub output: Vec<Expression>,
+    pub diagnostics: Vec<Diagnostic>,
     position: usize,
-    lenght: usize
+    lenght: usize,
+    function_depth: usize, // number of function bodies around the current token
+    loop_depth: usize // number of loop bodies around the current token inside the innermost function
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(input: &'a Vec<Token>) -> Parser {
+    pub fn new(input: &'a [Token]) -> Parser<'a> {
         Parser {
-            input: input,
+            input,
             output: Vec::new(),
+            diagnostics: Vec::new(),
             position: 0,
-            lenght: input.len()
+            lenght: input.len(),
+            function_depth: 0,
+            loop_depth: 0
         }
     }
 
@@ -31,157 +94,703 @@ impl<'a> Parser<'a> {
         true
     }
 
+    fn expect(&mut self, token_type: TokenType) -> Result<Token, Diagnostic> {
+        let curr_token = self.peek(0);
+
+        if !self.match_type(token_type.clone()) {
+            return Err(Diagnostic::unexpected_token(vec![token_type], &curr_token));
+        }
+
+        Ok(curr_token)
+    }
+
     fn peek(&self, relative_position: i32) -> Token {
         let pos = (self.position as i32 + relative_position) as usize;
 
         if pos >= self.lenght {
-            return Token::new(TokenType::EOF, String::new());
+            let span = match self.input.last() {
+                Some(token) => Span::new(token.span.end, token.span.end, token.span.line, token.span.column),
+                None => Span::default()
+            };
+
+            return Token::new(TokenType::EOF, String::new(), span);
         }
 
         self.input[pos].clone()
     }
 
-    fn expression(&mut self) -> Box<Expression> {
-        self.additive()
+    // span from the start token up to the last consumed token
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.peek(-1).span)
+    }
+
+    // lines ending in an operator or `<-` continue on the next one
+    fn skip_newlines(&mut self) {
+        while self.match_type(TokenType::Newline) { }
+    }
+
+    // skips newlines only when `token_type` follows them, so `else` may start a new line
+    fn match_after_newlines(&mut self, token_type: TokenType) -> bool {
+        let mut i = 0;
+
+        while self.peek(i).token_type == TokenType::Newline {
+            i += 1;
+        }
+
+        if self.peek(i).token_type != token_type {
+            return false;
+        }
+
+        self.position += i as usize + 1;
+        true
+    }
+
+    // a statement ends at a newline, `;`, the `}` of its block or the end of file,
+    // the line check catches new lines the lexer swallowed after an unclosed `(` or `[`
+    fn at_statement_end(&self) -> bool {
+        matches!(self.peek(0).token_type, TokenType::Newline | TokenType::Semicolon | TokenType::RightBrace | TokenType::EOF)
+            || self.starts_line()
     }
 
-    fn additive(&mut self) -> Box<Expression> {
-        let mut expr = self.multiplicative();
+    // whether only separators are left before the `}` of the current block
+    fn at_block_end(&self) -> bool {
+        let mut i = 0;
+
+        while matches!(self.peek(i).token_type, TokenType::Newline | TokenType::Semicolon) {
+            i += 1;
+        }
+
+        self.peek(i).token_type == TokenType::RightBrace
+    }
+
+    fn starts_line(&self) -> bool {
+        self.peek(0).span.line != self.peek(-1).span.line
+    }
+
+    // an unclosed `(` or `[` ends where a new line starts with `name <-`
+    fn at_unclosed_group_end(&self) -> bool {
+        self.starts_line() && self.peek(0).token_type == TokenType::VariableKey && self.peek(1).token_type == TokenType::Setter
+    }
+
+    // `f(x)` is a call but `f (x)` is two expressions
+    fn is_adjacent(&self) -> bool {
+        self.peek(0).span.start == self.peek(-1).span.end
+    }
+
+    fn skip_separators(&mut self) {
+        while self.match_type(TokenType::Newline) || self.match_type(TokenType::Semicolon) { }
+    }
+
+    // skips tokens up to the next statement boundary: a separator, `}`, an assignment
+    // or a new line, braces opened on the way are skipped as a whole
+    fn synchronize(&mut self, start_position: usize) {
+        if self.position == start_position {
+            self.position += 1;
+        }
+
+        let mut depth = 0;
 
         loop {
-            if self.match_type(TokenType::Add) {
-                expr = Box::new(Expression::Binary(TokenType::Add, expr, self.multiplicative()));
-                continue;
+            let curr_token = self.peek(0);
+
+            match curr_token.token_type {
+                TokenType::EOF => break,
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace if depth == 0 => break,
+                TokenType::RightBrace => depth -= 1,
+                _ if depth > 0 => { },
+                TokenType::Newline | TokenType::Semicolon => break,
+                TokenType::VariableKey if self.peek(1).token_type == TokenType::Setter => break,
+                _ if self.starts_line() => break,
+                _ => { }
+            }
+
+            self.position += 1;
+        }
+    }
+
+    fn statement(&mut self) -> Expression {
+        let start_position = self.position;
+        let start = self.peek(0).span;
+        let lambda = self.is_lambda();
+
+        let result = match self.expression() {
+            Ok(_) if !self.at_statement_end() => {
+                Err(Diagnostic::unexpected_token(vec![TokenType::Newline, TokenType::Semicolon], &self.peek(0))
+                    .with_help("statements are separated by new lines or `;`"))
+            },
+            // only the last statement of a block gives its value away
+            Ok(_) if lambda && !self.at_block_end() => {
+                let message = "lambda is defined and thrown away".to_string();
+
+                Err(Diagnostic::new(DiagnosticKind::UnusedLambda, self.span_from(start), message)
+                    .with_help("`(a) -> f` defines a function, write `a -> f` to pipe `a` into `f`"))
+            },
+            result => result
+        };
+
+        match result {
+            Ok(expr) => *expr,
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                self.synchronize(start_position);
+
+                Expression::Error(self.span_from(start))
             }
-            if self.match_type(TokenType::Substract) {
-                expr = Box::new(Expression::Binary(TokenType::Substract, expr, self.multiplicative()));
-                continue;
+        }
+    }
+
+    // a function, `if` or loop body, where `{}` is an empty block rather than an empty map
+    fn body(&mut self) -> ParseResult {
+        self.skip_newlines();
+
+        let start = self.peek(0).span;
+        let mut i = 1;
+
+        while self.peek(i).token_type == TokenType::Newline {
+            i += 1;
+        }
+
+        if self.peek(0).token_type == TokenType::LeftBrace && self.peek(i).token_type == TokenType::RightBrace {
+            self.position += i as usize + 1;
+            return Ok(Box::new(Expression::Block(Vec::new(), self.span_from(start))));
+        }
+
+        self.expression()
+    }
+
+    fn loop_body(&mut self) -> ParseResult {
+        self.loop_depth += 1;
+        let body = self.body();
+        self.loop_depth -= 1;
+
+        body
+    }
+
+    fn expression(&mut self) -> ParseResult {
+        let expr = self.binary(0)?;
+
+        // `name <- value` is handled by `primary`, other targets are only known once parsed
+        if self.peek(0).token_type == TokenType::Setter {
+            if let Expression::Index(target, index, _) = *expr {
+                self.position += 1;
+                self.skip_newlines();
+
+                let value = self.expression()?;
+                let span = target.span().to(value.span());
+                return Ok(Box::new(Expression::SetIndex(target, index, value, span)));
             }
-            break;
+
+            return Err(Diagnostic::unexpected_token(vec![], &self.peek(0))
+                .with_help("only variables, list items and map keys can be assigned to"));
+        }
+
+        // `name ><`, `xs[i] ><` and `m.key ><` unbind what they name
+        if self.peek(0).token_type == TokenType::Remover {
+            let remover = self.peek(0);
+
+            return match *expr {
+                Expression::GetVariable(_, _) | Expression::Index(_, _, _) => {
+                    self.position += 1;
+
+                    let span = expr.span().to(remover.span);
+                    Ok(Box::new(Expression::Remove(expr, span)))
+                },
+                _ => Err(Diagnostic::unexpected_token(vec![], &remover)
+                    .with_help("only variables, list items and map keys can be removed"))
+            };
         }
 
-        expr
+        Ok(expr)
     }
 
-    fn multiplicative(&mut self) -> Box<Expression> {
-        let mut expr = self.unary();
+    // precedence climbing over `binary_operator`, only operators binding at least as tight as
+    // `min_precedence` are consumed so the caller keeps the looser ones
+    fn binary(&mut self, min_precedence: u8) -> ParseResult {
+        let mut expr = self.prefix()?;
 
         loop {
-            if self.match_type(TokenType::Multiply) {
-                expr = Box::new(Expression::Binary(TokenType::Multiply, expr, self.multiplicative()));
-                continue;
-            }
-            if self.match_type(TokenType::Divide) {
-                expr = Box::new(Expression::Binary(TokenType::Divide, expr, self.multiplicative()));
-                continue;
+            let op_type = self.peek(0).token_type;
+
+            let (precedence, associativity) = match binary_operator(&op_type) {
+                Some((precedence, associativity)) if precedence >= min_precedence => (precedence, associativity),
+                _ => break
+            };
+
+            self.position += 1;
+            self.skip_newlines();
+
+            let right = match associativity {
+                Associativity::Left => self.binary(precedence + 1)?,
+                Associativity::Right => self.binary(precedence)?
+            };
+            let span = expr.span().to(right.span());
+
+            expr = match op_type {
+                TokenType::ActionPointer => pipe(*expr, right)?,
+                TokenType::And | TokenType::Or => Box::new(Expression::Logical(op_type, expr, right, span)),
+                _ => Box::new(Expression::Binary(op_type, expr, right, span))
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn prefix(&mut self) -> ParseResult {
+        let start = self.peek(0).span;
+        let op_type = self.peek(0).token_type;
+
+        match prefix_operator(&op_type) {
+            Some(precedence) => {
+                self.position += 1;
+
+                // a literal takes its sign unless `**` or a call, index or key binds it first,
+                // so `-9223372036854775808` is the smallest integer instead of an overflow
+                let postfix = matches!(self.peek(1).token_type, TokenType::Power | TokenType::LeftParen | TokenType::LeftBracket | TokenType::Dot);
+
+                if op_type == TokenType::Substract && self.is_integer_literal() && !postfix {
+                    return self.integer_literal(start, true);
+                }
+
+                // the operand holds everything binding tighter than the operator itself
+                let expr = self.binary(precedence + 1)?;
+                Ok(Box::new(Expression::Unary(op_type, expr, self.span_from(start))))
+            },
+            None => self.call()
+        }
+    }
+
+    fn is_integer_literal(&self) -> bool {
+        matches!(self.peek(0).token_type, TokenType::Integer | TokenType::HexNumber | TokenType::OctNumber | TokenType::BinNumber)
+    }
+
+    // `negative` when a `-` in front of the literal was folded into it
+    fn integer_literal(&mut self, start: Span, negative: bool) -> ParseResult {
+        let curr_token = self.peek(0);
+        let radix = match curr_token.token_type {
+            TokenType::HexNumber => 16,
+            TokenType::OctNumber => 8,
+            TokenType::BinNumber => 2,
+            _ => 10
+        };
+
+        self.position += 1;
+
+        // the full 64 bits are allowed so masks like 0xFFFFFFFFFFFFFFFF keep their bit pattern,
+        // decimals reach 2^63 only with a `-`, which makes the smallest integer
+        let limit = if radix != 10 { u64::MAX } else if negative { 1 << 63 } else { i64::MAX as u64 };
+        let value = u64::from_str_radix(&curr_token.data, radix).ok().filter(|value| *value <= limit);
+
+        match value {
+            Some(value) if negative => Ok(Box::new(Expression::IntValue((value as i64).wrapping_neg(), self.span_from(start)))),
+            Some(value) => Ok(Box::new(Expression::IntValue(value as i64, start))),
+            None if radix == 10 => Err(Diagnostic::new(DiagnosticKind::MalformedNumber, self.span_from(start), format!("integer `{}` does not fit in 64 bits", curr_token.data))
+                .with_help("write it with a fraction, as in `1.0`, to get an approximate number")),
+            None => Err(Diagnostic::new(DiagnosticKind::MalformedNumber, self.span_from(start), format!("{} does not fit in 64 bits", curr_token.token_type)))
+        }
+    }
+
+    fn call(&mut self) -> ParseResult {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.is_adjacent() && self.match_type(TokenType::LeftParen) {
+                let mut args = Vec::new();
+
+                loop {
+                    if self.match_type(TokenType::RightParen) {
+                        break;
+                    } else if self.peek(0).token_type == TokenType::EOF || self.at_unclosed_group_end() {
+                        return Err(Diagnostic::unexpected_token(vec![TokenType::RightParen], &self.peek(0)));
+                    } else {
+                        args.push(*self.expression()?);
+                        self.match_type(TokenType::Comma);
+                    }
+                }
+
+                let span = self.span_from(expr.span());
+                expr = Box::new(Expression::Call(expr, args, span));
+            } else if self.is_adjacent() && self.match_type(TokenType::LeftBracket) {
+                let start = if self.peek(0).token_type == TokenType::Colon { None } else { Some(self.expression()?) };
+
+                if self.match_type(TokenType::Colon) {
+                    let end = if self.peek(0).token_type == TokenType::RightBracket { None } else { Some(self.expression()?) };
+
+                    self.expect(TokenType::RightBracket)?;
+                    let span = self.span_from(expr.span());
+                    expr = Box::new(Expression::Slice(expr, start, end, span));
+                } else if let Some(index) = start {
+                    self.expect(TokenType::RightBracket)?;
+                    let span = self.span_from(expr.span());
+                    expr = Box::new(Expression::Index(expr, index, span));
+                }
+            } else if self.match_type(TokenType::Dot) {
+                // `m.key` is `m["key"]`
+                let key = self.expect(TokenType::VariableKey)?;
+                let span = self.span_from(expr.span());
+                expr = Box::new(Expression::Index(expr, Box::new(Expression::StringValue(key.data, key.span)), span));
+            } else {
+                break;
             }
-            break;
         }
 
-        expr
+        Ok(expr)
+    }
+
+    // `{}` and `{ key: value }` are maps, any other braces are a block
+    fn is_map_literal(&self) -> bool {
+        let mut i = 1;
+
+        while self.peek(i).token_type == TokenType::Newline {
+            i += 1;
+        }
+
+        if self.peek(i).token_type == TokenType::RightBrace {
+            return true;
+        }
+
+        match self.literal_key_end(i) {
+            Some(end) => self.peek(end).token_type == TokenType::Colon,
+            None => false
+        }
+    }
+
+    // position just past a literal that can be a map key starting at `i`: a name,
+    // a possibly signed number, `true`, `false`, `null` or a string, interpolated or not
+    fn literal_key_end(&self, i: i32) -> Option<i32> {
+        match self.peek(i).token_type {
+            TokenType::Substract | TokenType::Add => match self.peek(i + 1).token_type {
+                TokenType::Number | TokenType::Integer | TokenType::HexNumber |
+                TokenType::OctNumber | TokenType::BinNumber => Some(i + 2),
+                _ => None
+            },
+            TokenType::Number | TokenType::Integer | TokenType::HexNumber | TokenType::OctNumber |
+            TokenType::BinNumber | TokenType::StringValue | TokenType::True | TokenType::False |
+            TokenType::Null | TokenType::VariableKey => Some(i + 1),
+            TokenType::StringPart => {
+                let mut depth = 0;
+                let mut j = i;
+
+                loop {
+                    match self.peek(j).token_type {
+                        TokenType::StringPart => depth += 1,
+                        TokenType::InterpolationEnd => depth -= 1,
+                        TokenType::StringValue if depth == 0 => return Some(j + 1),
+                        TokenType::EOF => return None,
+                        _ => { }
+                    }
+
+                    j += 1;
+                }
+            },
+            _ => None
+        }
+    }
+
+    // names stand for the string of the name, other keys are literals
+    fn map_literal(&mut self) -> ParseResult {
+        let start = self.peek(0).span;
+        let mut pairs = Vec::new();
+
+        self.expect(TokenType::LeftBrace)?;
+        self.skip_newlines();
+
+        while !self.match_type(TokenType::RightBrace) {
+            let curr_token = self.peek(0);
+
+            let key = match curr_token.token_type {
+                TokenType::VariableKey => {
+                    self.position += 1;
+                    Box::new(Expression::StringValue(curr_token.data, curr_token.span))
+                },
+                _ if self.literal_key_end(0).is_some() => self.prefix()?,
+                _ => return Err(Diagnostic::unexpected_token(vec![TokenType::RightBrace, TokenType::VariableKey, TokenType::StringValue], &curr_token)
+                    .with_help("map keys are names, numbers, strings, `true`, `false` or `null`"))
+            };
+
+            self.expect(TokenType::Colon)?;
+            self.skip_newlines();
+            let value = self.expression()?;
+            self.match_type(TokenType::Comma);
+            self.skip_newlines();
+
+            pairs.push((*key, *value));
+        }
+
+        Ok(Box::new(Expression::MapValue(pairs, self.span_from(start))))
     }
 
-    fn unary(&mut self) -> Box<Expression> {
-        if self.match_type(TokenType::Substract) {
-            return Box::new(Expression::Unary(TokenType::Substract, self.primary()));
+    // parameters after the `(` of a function, up to and including the `)`
+    fn parameters(&mut self) -> Result<Vec<Parameter>, Diagnostic> {
+        let mut params: Vec<Parameter> = Vec::new();
+
+        loop {
+            let curr_token = self.peek(0);
+
+            if self.match_type(TokenType::VariableKey) {
+                let default = if self.match_type(TokenType::Setter) {
+                    Some(self.expression()?)
+                } else {
+                    None
+                };
+
+                if default.is_none() && params.iter().any(|arg| arg.default.is_some()) {
+                    let message = format!("parameter `{}` without a default follows a parameter with a default", curr_token.data);
+                    return Err(Diagnostic::new(DiagnosticKind::InvalidParameter, curr_token.span, message));
+                }
+
+                params.push(Parameter { name: curr_token.data, default, rest: false });
+                self.match_type(TokenType::Comma);
+            } else if self.match_type(TokenType::Ellipsis) {
+                let name = self.expect(TokenType::VariableKey)?.data;
+
+                params.push(Parameter { name, default: None, rest: true });
+
+                self.expect(TokenType::RightParen)
+                    .map_err(|error| error.with_help("the rest parameter must be the last one"))?;
+                break;
+            } else if self.match_type(TokenType::RightParen) {
+                break;
+            } else {
+                return Err(Diagnostic::unexpected_token(vec![TokenType::RightParen, TokenType::VariableKey], &curr_token)
+                    .with_help("parameters are separated by spaces or commas, as in `function(a b)`"));
+            }
         }
-        if self.match_type(TokenType::Add) {
-            return Box::new(Expression::Unary(TokenType::Add, self.primary()));
+
+        Ok(params)
+    }
+
+    fn function_body(&mut self, params: Vec<Parameter>, start: Span) -> ParseResult {
+        let loop_depth = self.loop_depth;
+
+        self.function_depth += 1;
+        self.loop_depth = 0;
+        let expr = self.body();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+
+        Ok(Box::new(Expression::Function(expr?, params, self.span_from(start))))
+    }
+
+    // a parenthesized parameter list followed by `->`
+    fn is_lambda(&self) -> bool {
+        if self.peek(0).token_type != TokenType::LeftParen {
+            return false;
         }
 
-        self.primary()
+        let mut i = 1;
+
+        loop {
+            match self.peek(i).token_type {
+                TokenType::VariableKey | TokenType::Comma | TokenType::Ellipsis => i += 1,
+                TokenType::RightParen => return self.peek(i + 1).token_type == TokenType::ActionPointer,
+                // past a default the list ends at the `)` that closes it, as in `function(...)`
+                TokenType::Setter if self.peek(i - 1).token_type == TokenType::VariableKey => {
+                    let mut depth = 0;
+
+                    loop {
+                        i += 1;
+
+                        match self.peek(i).token_type {
+                            TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+                            TokenType::RightParen if depth == 0 => return self.peek(i + 1).token_type == TokenType::ActionPointer,
+                            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => depth -= 1,
+                            TokenType::EOF => return false,
+                            _ => { }
+                        }
+                    }
+                },
+                _ => return false
+            }
+        }
     }
 
-    fn primary(&mut self) -> Box<Expression> {
-        let mut curr_token = self.peek(0);
+    fn primary(&mut self) -> ParseResult {
+        let curr_token = self.peek(0);
+        let start = curr_token.span;
+
+        if self.match_type(TokenType::LeftBracket) {
+            let mut items = Vec::new();
+
+            while !self.match_type(TokenType::RightBracket) {
+                if self.peek(0).token_type == TokenType::EOF || self.at_unclosed_group_end() {
+                    return Err(Diagnostic::unexpected_token(vec![TokenType::RightBracket], &self.peek(0)));
+                }
+
+                items.push(*self.expression()?);
+                self.match_type(TokenType::Comma);
+            }
+
+            return Ok(Box::new(Expression::ListValue(items, self.span_from(start))));
+        }
+
+        if curr_token.token_type == TokenType::LeftBrace && self.is_map_literal() {
+            return self.map_literal();
+        }
+
         if self.match_type(TokenType::LeftBrace) {
             let mut exprs = Vec::new();
 
-            while !self.match_type(TokenType::RightBrace) {
-                let expr = self.expression();
+            loop {
+                self.skip_separators();
+
+                if self.match_type(TokenType::RightBrace) {
+                    break;
+                } else if self.peek(0).token_type == TokenType::EOF {
+                    return Err(Diagnostic::unexpected_token(vec![TokenType::RightBrace], &self.peek(0)));
+                }
+
+                let expr = self.statement();
 
                 exprs.push(expr);
             }
 
-            return Box::new(Expression::Block(exprs));
+            return Ok(Box::new(Expression::Block(exprs, self.span_from(start))));
         }
         if self.match_type(TokenType::Func) {
-            let mut args = Vec::new();
+            let params = if self.match_type(TokenType::LeftParen) { self.parameters()? } else { Vec::new() };
 
-            if self.match_type(TokenType::LeftParen) {
-                loop {
-                    curr_token = self.peek(0);
+            return self.function_body(params, start);
+        }
 
-                    if self.match_type(TokenType::VariableKey) {
-                        let arg_name = curr_token.data;
+        // `(a b) -> a + b` is a short function
+        if self.is_lambda() {
+            self.expect(TokenType::LeftParen)?;
+            let params = self.parameters()?;
+            self.expect(TokenType::ActionPointer)?;
 
-                        args.push(arg_name);
-                    } else if self.match_type(TokenType::RightParen) {
-                        break;
-                    } else {
-                        let token_type = curr_token.token_type;
+            return self.function_body(params, start);
+        }
 
-                        panic!("Expected {:?} or {:?}, found {:?}", TokenType::RightParen, TokenType::VariableKey, token_type);
-                    }
-                }
+        if self.match_type(TokenType::Return) {
+            if self.function_depth == 0 {
+                let message = "`return` outside of a function".to_string();
+                return Err(Diagnostic::new(DiagnosticKind::MisplacedKeyword, start, message));
             }
 
-            let expr = self.expression();
+            // a bare `return` ends its statement
+            let expr = if self.at_statement_end() || self.peek(0).token_type == TokenType::RightParen {
+                None
+            } else {
+                Some(self.expression()?)
+            };
+
+            return Ok(Box::new(Expression::Return(expr, self.span_from(start))));
+        }
+        if self.match_type(TokenType::If) {
+            let condition = self.expression()?;
+            let then_branch = self.body()?;
+            let else_branch = if self.match_after_newlines(TokenType::Else) {
+                Some(self.body()?)
+            } else {
+                None
+            };
+
+            return Ok(Box::new(Expression::If(condition, then_branch, else_branch, self.span_from(start))));
+        }
+        if self.match_type(TokenType::While) {
+            let condition = self.expression()?;
+            let body = self.loop_body()?;
+
+            return Ok(Box::new(Expression::While(condition, body, self.span_from(start))));
+        }
+        if self.match_type(TokenType::For) {
+            let name = self.expect(TokenType::VariableKey)?.data;
+            self.expect(TokenType::In)?;
+            let iterable = self.expression()?;
+            let body = self.loop_body()?;
+
+            return Ok(Box::new(Expression::For(name, iterable, body, self.span_from(start))));
+        }
+        if self.match_type(TokenType::Break) || self.match_type(TokenType::Continue) {
+            if self.loop_depth == 0 {
+                let message = format!("`{}` outside of a loop", curr_token.token_type);
+                return Err(Diagnostic::new(DiagnosticKind::MisplacedKeyword, start, message));
+            }
 
-            return Box::new(Expression::Function(expr, args));
+            return match curr_token.token_type {
+                TokenType::Break => Ok(Box::new(Expression::Break(start))),
+                _ => Ok(Box::new(Expression::Continue(start)))
+            };
         }
         if self.match_type(TokenType::Null) {
-            return Box::new(Expression::Null);
+            return Ok(Box::new(Expression::Null(start)));
+        }
+        if self.match_type(TokenType::True) {
+            return Ok(Box::new(Expression::BoolValue(true, start)));
+        }
+        if self.match_type(TokenType::False) {
+            return Ok(Box::new(Expression::BoolValue(false, start)));
         }
         if self.match_type(TokenType::Number) {
-            return Box::new(Expression::NumberValue(curr_token.data.parse().unwrap()));
+            return match curr_token.data.parse() {
+                Ok(value) => Ok(Box::new(Expression::NumberValue(value, start))),
+                Err(_) => Err(Diagnostic::new(DiagnosticKind::MalformedNumber, start, format!("invalid number `{}`", curr_token.data)))
+            };
         }
         if self.match_type(TokenType::StringValue) {
-            return Box::new(Expression::StringValue(curr_token.data));
+            return Ok(Box::new(Expression::StringValue(curr_token.data, start)));
         }
-        if self.match_type(TokenType::VariableKey) {
-            if self.match_type(TokenType::Setter) {
-                let var_key_offset = -2;
 
-                return Box::new(Expression::SetVariable(self.peek(var_key_offset).data, self.expression()));
-            }
-            if self.match_type(TokenType::Remover) {
-                let var_key_offset = -2;
+        // `"a ${b} c"` arrives as `a ` part, the tokens of `b`, `}` and the final ` c` string
+        if self.match_type(TokenType::StringPart) {
+            let mut parts = vec![Expression::StringValue(curr_token.data, start)];
 
-                return Box::new(Expression::SetVariable(self.peek(var_key_offset).data, Box::new(Expression::Null)));
-            }
-            if self.match_type(TokenType::LeftParen) {
-                let mut args = Vec::new();
+            loop {
+                parts.push(*self.expression()?);
+                self.expect(TokenType::InterpolationEnd)?;
 
-                loop {
-                    if self.match_type(TokenType::RightParen) {
-                        break;
-                    } else {
-                        args.push(self.expression());
-                    }
+                let part = self.peek(0);
+
+                if self.match_type(TokenType::StringPart) {
+                    parts.push(Expression::StringValue(part.data, part.span));
+                } else {
+                    let part = self.expect(TokenType::StringValue)?;
+                    parts.push(Expression::StringValue(part.data, part.span));
+                    break;
                 }
+            }
+
+            return Ok(Box::new(Expression::Interpolation(parts, self.span_from(start))));
+        }
+        if self.match_type(TokenType::VariableKey) {
+            if self.match_type(TokenType::Setter) {
+                self.skip_newlines();
+                let expr = self.expression()?;
 
-                return Box::new(Expression::CallFunc(curr_token.data, args));
+                return Ok(Box::new(Expression::SetVariable(curr_token.data, expr, self.span_from(start))));
             }
-            return Box::new(Expression::GetVariable(curr_token.data));
+            return Ok(Box::new(Expression::GetVariable(curr_token.data, start)));
         }
-        if self.match_type(TokenType::HexNumber) {
-            return Box::new(Expression::NumberValue(i64::from_str_radix(&curr_token.data, 16).unwrap() as f64));
+        if self.is_integer_literal() {
+            return self.integer_literal(start, false);
         }
         if self.match_type(TokenType::LeftParen) {
-            let expr = self.expression();
-            self.match_type(TokenType::RightParen);
-            return expr;
+            let expr = self.expression()?;
+            self.expect(TokenType::RightParen)?;
+            return Ok(expr);
         }
 
-        panic!("unknown expr");
+        Err(Diagnostic::expected_expression(&curr_token))
     }
 
-    pub fn parse(&mut self) {
-        while !self.match_type(TokenType::EOF) {
-            let expr = self.expression();
+    pub fn parse(&mut self) -> Result<(), Vec<Diagnostic>> {
+        loop {
+            self.skip_separators();
+
+            if self.match_type(TokenType::EOF) {
+                break;
+            }
+
+            let expr = self.statement();
 
             self.output.push(expr);
         }
+
+        if self.diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(self.diagnostics.clone())
+        }
     }
 }
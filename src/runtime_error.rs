@@ -0,0 +1,131 @@
+﻿use std::fmt::Formatter;
+use std::fmt::Display;
+use std::fmt::Error;
+use diagnostic;
+use span::Span;
+use value::Value;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeErrorKind {
+    TypeError,
+    ArityError,
+    UndefinedVariable,
+    NotCallable,
+    OverflowError,
+    DivisionByZero,
+    IndexError,
+    KeyError,
+    InvalidControlFlow,
+    SyntaxError // evaluation reached source that failed to parse
+}
+
+impl Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{:?}", self)
+    }
+}
+
+// a script function call that was active when the error happened
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub function: String,
+    pub span: Span, // call site
+    pub file: String
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        if self.span == Span::default() {
+            return write!(f, "{} called by a builtin", self.function);
+        }
+
+        write!(f, "{} at {}:{}", self.function, self.file, self.span)
+    }
+}
+
+// operands and kind are meant for hosts that inspect runtime errors
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub message: String,
+    pub operands: Vec<String>, // type names of the values involved
+    pub span: Span,
+    pub trace: Vec<Frame> // innermost call first
+}
+
+impl RuntimeError {
+    pub fn new(kind: RuntimeErrorKind, message: String) -> RuntimeError {
+        RuntimeError {
+            kind,
+            message,
+            operands: Vec::new(),
+            span: Span::default(),
+            trace: Vec::new()
+        }
+    }
+
+    pub fn type_error(operation: &str, operands: &[&Value]) -> RuntimeError {
+        let operands: Vec<String> = operands.iter().map(|value| value.type_name().to_string()).collect();
+        let message = format!("cannot {} {}", operation, operands.join(" and "));
+
+        RuntimeError {
+            kind: RuntimeErrorKind::TypeError,
+            message,
+            operands,
+            span: Span::default(),
+            trace: Vec::new()
+        }
+    }
+
+    pub fn overflow(operation: &str, operands: &[&Value]) -> RuntimeError {
+        let mut error = RuntimeError::type_error(operation, operands);
+
+        error.kind = RuntimeErrorKind::OverflowError;
+        error.message = format!("integer overflow when trying to {} {}", operation, error.operands.join(" and "));
+        error
+    }
+
+    pub fn division_by_zero(operation: &str, operands: &[&Value]) -> RuntimeError {
+        let mut error = RuntimeError::type_error(operation, operands);
+
+        error.kind = RuntimeErrorKind::DivisionByZero;
+        error.message = format!("division by zero when trying to {} {}", operation, error.operands.join(" and "));
+        error
+    }
+
+    pub fn at(mut self, span: Span) -> RuntimeError {
+        self.span = span;
+        self
+    }
+
+    pub fn in_frame(mut self, function: &str, span: Span, file: &str) -> RuntimeError {
+        self.trace.push(Frame {
+            function: function.to_string(),
+            span,
+            file: file.to_string()
+        });
+        self
+    }
+
+    pub fn render(&self, file_name: &str, source: &str, color: bool) -> String {
+        let title = format!("error[{}]", self.kind);
+        let mut output = diagnostic::render(&title, &self.message, self.span, None, file_name, source, color);
+
+        if !self.trace.is_empty() {
+            output += "stack trace:\n";
+
+            for (i, frame) in self.trace.iter().enumerate() {
+                output += &format!("{:>4}: {}\n", i, frame);
+            }
+        }
+
+        output
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{}: {}: {}", self.span, self.kind, self.message)
+    }
+}
@@ -0,0 +1,41 @@
+﻿use std::fmt::Formatter;
+use std::fmt::Display;
+use std::fmt::Error;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: usize, // byte offset of the first character
+    pub end: usize, // byte offset right after the last character
+    pub line: usize, // 1-based line of the first character
+    pub column: usize // 1-based column of the first character
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Span {
+        Span {
+            start,
+            end,
+            line,
+            column
+        }
+    }
+
+    pub fn to(&self, other: Span) -> Span {
+        if other.start < self.start {
+            return other.to(*self);
+        }
+
+        Span {
+            start: self.start,
+            end: other.end.max(self.end),
+            line: self.line,
+            column: self.column
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
@@ -1,10 +1,12 @@
 ﻿use value::Value;
 
 #[derive(Debug, Default)]
+#[allow(dead_code)]
 pub struct Stack {
     items: Vec<Value>,
 }
 
+#[allow(dead_code)]
 impl Stack {
     pub fn push(&mut self, value: Value) {
         self.items.push(value);
@@ -1,9 +1,11 @@
-﻿use expressions::Expression;
+﻿use builtins;
 use environment::{Environment, EnvironmentData};
 use std::{cell::RefCell, rc::Rc};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Formatter;
 use std::fmt::Display;
 use std::fmt::Error;
+use map::Map;
 use stack::Stack;
 use value::Value;
 
@@ -17,40 +19,54 @@ pub enum StorageVariable {
 pub struct Storage {
     items: Vec<Value>,
     last: usize,
+    free: Vec<usize>, // released slots of items, reused before growing
+    #[allow(dead_code)]
     stack: Stack,
-    env: Environment
+    env: Environment,
+    suspects: Vec<Environment>, // ended scopes something still holds, see `release`
+    suspect_limit: usize,
+    file: String, // script being run, used in stack traces
+    #[cfg(test)]
+    cycle_checks: usize
 }
 
+// suspects gathered before the first cycle check
+const SUSPECT_BATCH: usize = 32;
+
 pub struct VariableNotFoundError {
     key: String
 }
 
 impl Display for VariableNotFoundError {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        write!(f, "Variable not found: {}", self. key)
+        write!(f, "`{}` is not defined", self.key)
     }
 }
 
 impl Storage {
     pub fn init_std(&mut self) {
-        let print = Value::NativeFunc(|args: Vec<Box<Expression>>, storage: &mut Storage| -> Value {
-            let obj_to_print = args[0].eval(storage);
-
-            println!("{:?}", obj_to_print);
+        for (name, func) in builtins::FUNCTIONS.iter() {
+            self.declare(name.to_string(), Value::NativeFunc(*func));
+        }
+    }
 
-            Value::Null
-        });
+    pub fn set_file(&mut self, file: &str) {
+        self.file = file.to_string();
+    }
 
-        match self.store(StorageVariable::User("print".to_string()), print.clone()) {
-            Ok(_) => { },
-            Err(_) => {
-                self.new_var("print".to_string());
-                let _ = self.store(StorageVariable::User("print".to_string()), print);
-            }
-        }
+    pub fn file(&self) -> &str {
+        &self.file
     }
 
     pub fn get_free(&mut self) -> StorageVariable {
+        if self.free.is_empty() {
+            self.collect();
+        }
+
+        if let Some(result) = self.free.pop() {
+            return StorageVariable::Local(result);
+        }
+
         let result = self.last;
 
         self.items.push(Value::Null);
@@ -69,6 +85,25 @@ impl Storage {
         var
     }
 
+    pub fn declare(&mut self, key: String, value: Value) {
+        let var = self.new_var(key);
+
+        let _ = self.store(var, value);
+    }
+
+    // unbinds the nearest `key` and frees its slot, returns the value it held
+    pub fn remove(&mut self, key: String) -> Result<Value, VariableNotFoundError> {
+        let var = self.env.borrow_mut().remove(&key);
+
+        match var {
+            Some(var) => {
+                self.free.push(var);
+                Ok(std::mem::replace(&mut self.items[var], Value::Null))
+            },
+            None => Err(VariableNotFoundError { key })
+        }
+    }
+
     pub fn store(&mut self, var: StorageVariable, value: Value) -> Result<(), VariableNotFoundError> {
         match var {
             StorageVariable::Local(local) => {
@@ -86,28 +121,29 @@ impl Storage {
         }
     }
 
-    pub fn get(&self, var: StorageVariable) -> Value {
+    pub fn get(&self, var: StorageVariable) -> Result<Value, VariableNotFoundError> {
         match var {
-            StorageVariable::Local(local) => self.items[local].clone(),
+            StorageVariable::Local(local) => Ok(self.items[local].clone()),
             StorageVariable::User(ident) => {
                 let var = self
                     .env
                     .borrow()
                     .get(ident.clone());
 
-                if var.is_some() {
-                    self.items[var.unwrap()].clone()
-                } else {
-                    Value::Null
+                match var {
+                    Some(var) => Ok(self.items[var].clone()),
+                    None => Err(VariableNotFoundError { key: ident })
                 }
             }
         }
     }
 
+    #[allow(dead_code)]
     pub fn push(&mut self, value: Value) {
         self.stack.push(value);
     }
 
+    #[allow(dead_code)]
     pub fn pop(&mut self) -> Value {
         self.stack.pop()
     }
@@ -118,10 +154,310 @@ impl Storage {
         self.env = Rc::new(RefCell::new(data));
     }
 
+    pub fn env(&self) -> Environment {
+        Rc::clone(&self.env)
+    }
+
+    // starts a scope nested in `outer` instead of the current environment,
+    // returns the environment to bring back with `scope_restore`
+    pub fn scope_start_in(&mut self, outer: Environment) -> Environment {
+        let data = EnvironmentData::new(Some(outer));
+
+        std::mem::replace(&mut self.env, Rc::new(RefCell::new(data)))
+    }
+
+    pub fn scope_restore(&mut self, env: Environment) {
+        let ended = std::mem::replace(&mut self.env, env);
+
+        self.release(ended);
+    }
+
     pub fn scope_end(&mut self) {
         if self.env.borrow().outer.is_some() {
             let outer = Rc::clone(self.env.borrow().outer.as_ref().unwrap());
-            self.env = outer;
+            let ended = std::mem::replace(&mut self.env, outer);
+
+            self.release(ended);
+        }
+    }
+
+    // frees the slots of an ended scope, one something still holds waits for the next
+    // cycle check, in case the only things holding it are closures stored in it
+    fn release(&mut self, env: Environment) {
+        if Rc::strong_count(&env) != 1 {
+            self.suspects.push(env);
+
+            // checking in batches walks a big list shared by many scopes once per batch
+            if self.suspects.len() >= self.suspect_limit.max(SUSPECT_BATCH) {
+                self.release_cycles();
+                self.suspect_limit = 2 * self.suspects.len();
+            }
+        } else {
+            drop(env);
+        }
+
+        self.collect();
+    }
+
+    // frees the slots of dropped environments, dropping their values can release more
+    fn collect(&mut self) {
+        let released = self.env.borrow().released();
+
+        loop {
+            let slots = std::mem::take(&mut *released.borrow_mut());
+
+            if slots.is_empty() {
+                break;
+            }
+
+            for slot in slots {
+                self.items[slot] = Value::Null;
+                self.free.push(slot);
+            }
+        }
+    }
+
+    // trial deletion over everything reachable from the suspects without going through
+    // the current scopes: whatever is only referenced from inside that graph is garbage
+    fn release_cycles(&mut self) {
+        #[cfg(test)]
+        {
+            self.cycle_checks += 1;
+        }
+
+        let mut current = HashSet::new();
+        let mut scope = Some(Rc::clone(&self.env));
+
+        while let Some(env) = scope {
+            current.insert(Node::Env(Rc::clone(&env)).key());
+            scope = env.borrow().outer.clone();
         }
+
+        // a suspect can be current again while a closure it made is running
+        let roots: HashSet<usize> = self.suspects.iter().map(|env| Node::Env(Rc::clone(env)).key()).collect();
+        let mut nodes = HashMap::new();
+        let mut edges = HashMap::new();
+        let mut internal: HashMap<usize, usize> = HashMap::new();
+        let mut pending: Vec<Node> = self.suspects
+            .iter()
+            .filter(|env| !current.contains(&Node::Env(Rc::clone(env)).key()))
+            .map(|env| Node::Env(Rc::clone(env)))
+            .collect();
+
+        while let Some(node) = pending.pop() {
+            let key = node.key();
+
+            if nodes.contains_key(&key) {
+                continue;
+            }
+
+            let mut targets = Vec::new();
+
+            for child in self.children(&node) {
+                let child_key = child.key();
+
+                if current.contains(&child_key) {
+                    continue;
+                }
+
+                *internal.entry(child_key).or_insert(0) += 1;
+                targets.push(child_key);
+
+                if !nodes.contains_key(&child_key) {
+                    pending.push(child);
+                }
+            }
+
+            edges.insert(key, targets);
+            nodes.insert(key, node);
+        }
+
+        // one reference is the copy in `nodes`, suspects also have the one in `self.suspects`
+        let mut live: Vec<usize> = nodes
+            .iter()
+            .filter(|&(key, node)| {
+                let held = 1 + internal.get(key).cloned().unwrap_or(0) + if roots.contains(key) { 1 } else { 0 };
+
+                node.strong_count() > held
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        let mut reached: HashSet<usize> = live.iter().cloned().collect();
+
+        while let Some(key) = live.pop() {
+            for target in &edges[&key] {
+                if reached.insert(*target) {
+                    live.push(*target);
+                }
+            }
+        }
+
+        let mut slots = Vec::new();
+
+        for (key, node) in &nodes {
+            if let Node::Env(ref env) = *node {
+                if !reached.contains(key) {
+                    slots.extend(env.borrow_mut().clear());
+                }
+            }
+        }
+
+        drop(nodes);
+        self.suspects.retain(|env| {
+            let key = Node::Env(Rc::clone(env)).key();
+
+            current.contains(&key) || reached.contains(&key)
+        });
+
+        for slot in slots {
+            self.items[slot] = Value::Null;
+            self.free.push(slot);
+        }
+    }
+
+    // environments, lists and maps directly referenced by `node`
+    fn children(&self, node: &Node) -> Vec<Node> {
+        let mut children = Vec::new();
+
+        match *node {
+            Node::Env(ref env) => {
+                let env = env.borrow();
+
+                if let Some(ref outer) = env.outer {
+                    children.push(Node::Env(Rc::clone(outer)));
+                }
+
+                for slot in env.slots() {
+                    children.extend(Node::of(&self.items[slot]));
+                }
+            },
+            Node::List(ref items) => {
+                for item in items.borrow().iter() {
+                    children.extend(Node::of(item));
+                }
+            },
+            Node::Map(ref entries) => {
+                for (key, value) in entries.borrow().entries() {
+                    children.extend(Node::of(key));
+                    children.extend(Node::of(value));
+                }
+            }
+        }
+
+        children
+    }
+}
+
+// something a closure can be kept alive through
+enum Node {
+    Env(Environment),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Map>>)
+}
+
+impl Node {
+    fn of(value: &Value) -> Option<Node> {
+        match *value {
+            Value::Function(_, _, ref env) => Some(Node::Env(Rc::clone(env))),
+            Value::List(ref items) => Some(Node::List(Rc::clone(items))),
+            Value::Map(ref entries) => Some(Node::Map(Rc::clone(entries))),
+            _ => None
+        }
+    }
+
+    fn key(&self) -> usize {
+        match *self {
+            Node::Env(ref env) => Rc::as_ptr(env) as *const u8 as usize,
+            Node::List(ref items) => Rc::as_ptr(items) as *const u8 as usize,
+            Node::Map(ref entries) => Rc::as_ptr(entries) as *const u8 as usize
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match *self {
+            Node::Env(ref env) => Rc::strong_count(env),
+            Node::List(ref items) => Rc::strong_count(items),
+            Node::Map(ref entries) => Rc::strong_count(entries)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lexer::Lexer;
+    use parser::Parser;
+    use super::{Storage, StorageVariable, SUSPECT_BATCH};
+
+    fn run(source: &str) -> Storage {
+        let mut lexer = Lexer::new(source);
+        assert!(lexer.tokenize().is_ok());
+
+        let mut parser = Parser::new(&lexer.output);
+        assert!(parser.parse().is_ok());
+
+        let mut storage = Storage::default();
+        storage.init_std();
+
+        for expr in parser.output {
+            assert!(expr.eval(&mut storage).is_ok());
+        }
+
+        storage
+    }
+
+    #[test]
+    fn closure_stored_in_its_own_scope_is_freed() {
+        let storage = run("i <- 0\nwhile i < 10000 { f <- () -> i; i <- i + 1 }");
+
+        assert!(storage.items.len() < 100, "{} slots still allocated", storage.items.len());
+    }
+
+    #[test]
+    fn local_helper_is_freed() {
+        let storage = run(
+            "g <- function(n) {\n    helper <- (x) -> x + 1\n    helper(n)\n}\ni <- 0\nwhile i < 10000 { g(i); i <- i + 1 }"
+        );
+
+        assert!(storage.items.len() < 100, "{} slots still allocated", storage.items.len());
+    }
+
+    #[test]
+    fn overwritten_closure_is_freed() {
+        let storage = run("f <- null\ni <- 0\nwhile i < 10000 { x <- i; f <- () -> x; i <- i + 1 }");
+
+        assert!(storage.items.len() < 100, "{} slots still allocated", storage.items.len());
+    }
+
+    fn value_of(storage: &Storage, key: &str) -> String {
+        match storage.get(StorageVariable::User(key.to_string())) {
+            Ok(value) => value.to_string(),
+            Err(error) => panic!("{}", error)
+        }
+    }
+
+    #[test]
+    fn big_shared_list_is_walked_once_per_batch() {
+        let storage = run(
+            "big <- []\ni <- 0\nwhile i < 20000 { push(big i); i <- i + 1 }\nj <- 0\nwhile j < 2000 { ys <- big; g <- () -> ys; j <- j + 1 }"
+        );
+
+        assert!(storage.cycle_checks <= 2000 / SUSPECT_BATCH, "{} cycle checks", storage.cycle_checks);
+        assert!(storage.items.len() < 100, "{} slots still allocated", storage.items.len());
+    }
+
+    #[test]
+    fn escaped_closure_keeps_its_scope() {
+        let storage = run(
+            "make <- function(n) {\n    xs <- [n]\n    () -> xs\n}\nf <- make(5)\ng <- make(6)\nresult <- f()"
+        );
+
+        assert_eq!(value_of(&storage, "result"), "[5]");
+    }
+
+    #[test]
+    fn loop_closures_capture_their_own_item() {
+        let storage = run("fs <- []\nfor i in [1, 2, 3] push(fs () -> i)\nresult <- fs -> map((f) -> f())");
+
+        assert_eq!(value_of(&storage, "result"), "[1, 2, 3]");
     }
 }
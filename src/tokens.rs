@@ -1,21 +1,64 @@
-﻿#[derive(Clone)]
+﻿use span::Span;
+use std::fmt::Formatter;
+use std::fmt::Display;
+use std::fmt::Error;
+
+#[derive(Clone)]
 #[derive(Debug)]
 #[derive(PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TokenType {
-    Number, // any number in decimal notation
+    Number, // any number in decimal notation with a fraction
+    Integer, // any whole number in decimal notation
     HexNumber, // any number in hex notation
+    OctNumber, // any number in octal notation
+    BinNumber, // any number in binary notation
     VariableKey,
     StringValue,
+    StringPart, // text of an interpolated string before a `${`
+    InterpolationEnd, // } closing a `${`
     Null,
+    True, // true
+    False, // false
     Remover, // ><
     Setter, // <-
     Func, // function
+    Return, // return
+    If, // if
+    Else, // else
+    While, // while
+    For, // for
+    In, // in
+    Break, // break
+    Continue, // continue
     ActionPointer, // ->
+    Ellipsis, // ...
 
     Multiply, // *
     Divide, // /
     Add, // +
     Substract, // -
+    Modulo, // %
+    Power, // **
+    IntDivide, // ~/
+
+    BitAnd, // &
+    BitOr, // |
+    BitXor, // ^
+    BitNot, // ~
+    ShiftLeft, // <<
+    ShiftRight, // >>
+
+    Equal, // ==
+    NotEqual, // !=
+    Less, // <
+    LessEqual, // <=
+    Greater, // >
+    GreaterEqual, // >=
+
+    And, // and
+    Or, // or
+    Not, // not
 
     LeftParen, // (
     RightParen, // )
@@ -23,31 +66,85 @@ pub enum TokenType {
     LeftBrace, // {
     RightBrace, // }
 
+    LeftBracket, // [
+    RightBracket, // ]
+    Comma, // ,
+    Colon, // :
+    Dot, // .
+    Semicolon, // ;
+    Newline, // end of a line outside of parentheses and brackets
+
+    DocComment, // /// text, kept aside as trivia
+
     EOF // end of file
 }
 
-impl ToString for TokenType {
-    fn to_string(&self) -> String {
-        match self {
-            TokenType::Number => "Number".to_string(),
-            TokenType::HexNumber => "HexNumber".to_string(),
-            TokenType::VariableKey => "VariableKey".to_string(),
-            TokenType::StringValue => "StringValue".to_string(),
-            TokenType::Null => "null".to_string(),
-            TokenType::Remover => "><".to_string(),
-            TokenType::Setter => "<-".to_string(),
-            TokenType::Func => "function".to_string(),
-            TokenType::ActionPointer => "->".to_string(),
-            TokenType::Multiply => "*".to_string(),
-            TokenType::Divide => "/".to_string(),
-            TokenType::Add => "+".to_string(),
-            TokenType::Substract => "-".to_string(),
-            TokenType::LeftParen => "(".to_string(),
-            TokenType::RightParen => ")".to_string(),
-            TokenType::LeftBrace => "{".to_string(),
-            TokenType::RightBrace => "}".to_string(),
-            TokenType::EOF => "EOF".to_string()
-        }
+impl Display for TokenType {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        let name = match self {
+            TokenType::Number => "number",
+            TokenType::Integer => "integer",
+            TokenType::HexNumber => "hex number",
+            TokenType::OctNumber => "octal number",
+            TokenType::BinNumber => "binary number",
+            TokenType::VariableKey => "identifier",
+            TokenType::StringValue => "string",
+            TokenType::StringPart => "string",
+            TokenType::InterpolationEnd => "}",
+            TokenType::Null => "null",
+            TokenType::True => "true",
+            TokenType::False => "false",
+            TokenType::Remover => "><",
+            TokenType::Setter => "<-",
+            TokenType::Func => "function",
+            TokenType::Return => "return",
+            TokenType::If => "if",
+            TokenType::Else => "else",
+            TokenType::While => "while",
+            TokenType::For => "for",
+            TokenType::In => "in",
+            TokenType::Break => "break",
+            TokenType::Continue => "continue",
+            TokenType::ActionPointer => "->",
+            TokenType::Ellipsis => "...",
+            TokenType::Multiply => "*",
+            TokenType::Divide => "/",
+            TokenType::Add => "+",
+            TokenType::Substract => "-",
+            TokenType::Modulo => "%",
+            TokenType::Power => "**",
+            TokenType::IntDivide => "~/",
+            TokenType::BitAnd => "&",
+            TokenType::BitOr => "|",
+            TokenType::BitXor => "^",
+            TokenType::BitNot => "~",
+            TokenType::ShiftLeft => "<<",
+            TokenType::ShiftRight => ">>",
+            TokenType::Equal => "==",
+            TokenType::NotEqual => "!=",
+            TokenType::Less => "<",
+            TokenType::LessEqual => "<=",
+            TokenType::Greater => ">",
+            TokenType::GreaterEqual => ">=",
+            TokenType::And => "and",
+            TokenType::Or => "or",
+            TokenType::Not => "not",
+            TokenType::LeftParen => "(",
+            TokenType::RightParen => ")",
+            TokenType::LeftBrace => "{",
+            TokenType::RightBrace => "}",
+            TokenType::LeftBracket => "[",
+            TokenType::RightBracket => "]",
+            TokenType::Comma => ",",
+            TokenType::Colon => ":",
+            TokenType::Dot => ".",
+            TokenType::Semicolon => ";",
+            TokenType::Newline => "newline",
+            TokenType::DocComment => "doc comment",
+            TokenType::EOF => "end of file"
+        };
+
+        write!(f, "{}", name)
     }
 }
 
@@ -55,14 +152,16 @@ impl ToString for TokenType {
 #[derive(Debug)]
 pub struct Token {
     pub token_type : TokenType,
-    pub data: String
+    pub data: String,
+    pub span: Span
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, data: String) -> Token {
+    pub fn new(token_type: TokenType, data: String, span: Span) -> Token {
         Token {
-            token_type: token_type,
-            data: data
+            token_type,
+            data,
+            span
         }
     }
 }
@@ -1,2 +1,3 @@
 ﻿pub type Number = f64;
+pub type Int = i64;
 pub type Bool = bool;
@@ -1,96 +1,586 @@
-﻿use expressions::Expression;
-use types::{Number, Bool};
+﻿use environment::Environment;
+use expressions::{ Expression, Parameter };
+use runtime_error::RuntimeError;
+use types::{Number, Int, Bool};
 use std::fmt::Formatter;
 use storage::Storage;
 use std::fmt::Error;
 use std::fmt::Debug;
+use std::fmt::Display;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
 use std::ops::Neg;
 use std::ops::Add;
 use std::ops::Sub;
 use std::ops::Mul;
 use std::ops::Div;
+use std::ops::Rem;
+use std::ops::Not;
+use std::ops::BitAnd;
+use std::ops::BitOr;
+use std::ops::BitXor;
+use std::ops::Shl;
+use std::ops::Shr;
+use runtime_error::RuntimeErrorKind;
+use std::hash::{ Hash, Hasher };
+use map::Map;
 
 #[derive(Clone)]
 pub enum Value {
     Null,
 
+    Int(Int),
+
     Number(Number),
 
     Bool(Bool),
 
     String(String),
 
-    Function(Box<Expression>, Vec<String>),
-    NativeFunc(fn(Vec<Box<Expression>>, &mut Storage) -> Value)
+    List(Rc<RefCell<Vec<Value>>>),
+
+    Map(Rc<RefCell<Map>>),
+
+    Function(Box<Expression>, Vec<Parameter>, Environment), // body, parameters, defining environment
+    NativeFunc(fn(Vec<Value>, &mut Storage) -> Result<Value, RuntimeError>)
 }
 
-impl Debug for Value {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Int(_) => "int",
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Function(_, _, _) => "function",
+            Value::NativeFunc(_) => "function"
+        }
+    }
+
+    // null, false, zero, NaN, "" and empty lists and maps are falsy, everything else is truthy
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(value) => *value,
+            Value::Int(value) => *value != 0,
+            Value::Number(value) => *value != 0.0 && !value.is_nan(),
+            Value::String(value) => !value.is_empty(),
+            Value::List(items) => !items.borrow().is_empty(),
+            Value::Map(entries) => !entries.borrow().is_empty(),
+            Value::Function(_, _, _) | Value::NativeFunc(_) => true
+        }
+    }
+
+    // values that can be map keys, lists and maps can change so they can't
+    pub fn is_hashable(&self) -> bool {
+        match self {
+            Value::Null | Value::Int(_) | Value::Bool(_) | Value::String(_) => true,
+            Value::Number(value) => !value.is_nan(),
+            _ => false
+        }
+    }
+
+    // `xs[i]`, negative indices count from the end, `m[key]` looks up a key
+    pub fn index(&self, index: &Value) -> Result<Value, RuntimeError> {
+        match self {
+            Value::Map(entries) => entries.borrow().get(index).cloned().ok_or_else(|| missing_key(index)),
+            Value::List(items) => {
+                let items = items.borrow();
+
+                Ok(items[resolve_index(index, items.len())?].clone())
+            },
+            Value::String(value) => {
+                let chars: Vec<char> = value.chars().collect();
+
+                Ok(Value::String(chars[resolve_index(index, chars.len())?].to_string()))
+            },
+            value => Err(RuntimeError::type_error("index", &[value]))
+        }
+    }
+
+    // `xs[start:end]` copies the items in between, bounds past either end are clamped
+    pub fn slice(&self, start: Option<&Value>, end: Option<&Value>) -> Result<Value, RuntimeError> {
+        match self {
+            Value::List(items) => {
+                let items = items.borrow();
+                let (start, end) = slice_bounds(start, end, items.len())?;
+
+                Ok(Value::List(Rc::new(RefCell::new(items[start..end].to_vec()))))
+            },
+            Value::String(value) => {
+                let chars: Vec<char> = value.chars().collect();
+                let (start, end) = slice_bounds(start, end, chars.len())?;
+
+                Ok(Value::String(chars[start..end].iter().collect()))
+            },
+            value => Err(RuntimeError::type_error("slice", &[value]))
+        }
+    }
+
+    // `xs[i] <- value` and `m[key] <- value`, strings can't be changed in place
+    pub fn set_index(&self, index: &Value, value: Value) -> Result<(), RuntimeError> {
+        match self {
+            Value::Map(entries) => {
+                check_key(index)?;
+
+                entries.borrow_mut().insert(index.clone(), value);
+                Ok(())
+            },
+            Value::List(items) => {
+                let mut items = items.borrow_mut();
+                let index = resolve_index(index, items.len())?;
+
+                items[index] = value;
+                Ok(())
+            },
+            target => Err(RuntimeError::type_error("assign to an index of", &[target]))
+        }
+    }
+
+    // `xs[i] ><` and `m[key] ><`, returns the removed value
+    pub fn remove_index(&self, index: &Value) -> Result<Value, RuntimeError> {
+        match self {
+            Value::List(items) => {
+                let mut items = items.borrow_mut();
+                let index = resolve_index(index, items.len())?;
+
+                Ok(items.remove(index))
+            },
+            Value::Map(entries) => entries.borrow_mut().remove(index).ok_or_else(|| missing_key(index)),
+            target => Err(RuntimeError::type_error("remove an index of", &[target]))
+        }
+    }
+
+    // the values a `for` loop walks over
+    pub fn items(&self) -> Result<Vec<Value>, RuntimeError> {
+        match self {
+            Value::String(value) => Ok(value.chars().map(|ch| Value::String(ch.to_string())).collect()),
+            Value::List(items) => Ok(items.borrow().clone()),
+            Value::Map(entries) => Ok(entries.borrow().keys()),
+            value => Err(RuntimeError::type_error("iterate over", &[value]))
+        }
+    }
+
+    pub fn equals(&self, other: &Value) -> bool {
+        self.equals_in(other, &mut Vec::new())
+    }
+
+    // `seen` holds the lists and maps being compared further up, meeting a pair
+    // again means both sides loop back the same way, which doesn't make them differ
+    fn equals_in(&self, other: &Value, seen: &mut Vec<(usize, usize)>) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Int(value1), Value::Int(value2)) => value1 == value2,
+            (Value::Int(value1), Value::Number(value2)) => int_equals_number(*value1, *value2),
+            (Value::Number(value1), Value::Int(value2)) => int_equals_number(*value2, *value1),
+            (Value::Number(value1), Value::Number(value2)) => value1 == value2,
+            (Value::Bool(value1), Value::Bool(value2)) => value1 == value2,
+            (Value::String(value1), Value::String(value2)) => value1 == value2,
+            (Value::List(items1), Value::List(items2)) => {
+                let pair = (address(items1), address(items2));
+
+                if Rc::ptr_eq(items1, items2) || seen.contains(&pair) {
+                    return true;
+                }
+
+                let items1 = items1.borrow();
+                let items2 = items2.borrow();
+
+                seen.push(pair);
+                let result = items1.len() == items2.len() && items1.iter().zip(items2.iter()).all(|(item1, item2)| item1.equals_in(item2, seen));
+                seen.pop();
+
+                result
+            },
+            // key order doesn't matter for equality
+            (Value::Map(entries1), Value::Map(entries2)) => {
+                let pair = (address(entries1), address(entries2));
+
+                if Rc::ptr_eq(entries1, entries2) || seen.contains(&pair) {
+                    return true;
+                }
+
+                let entries1 = entries1.borrow();
+                let entries2 = entries2.borrow();
+
+                seen.push(pair);
+                let result = entries1.len() == entries2.len() && entries1.entries().iter().all(|(key, value1)| {
+                    entries2.get(key).is_some_and(|value2| value1.equals_in(value2, seen))
+                });
+                seen.pop();
+
+                result
+            },
+            (_, _) => false
+        }
+    }
+
+    // None when the values are unordered, as with NaN
+    pub fn compare(&self, other: &Value) -> Result<Option<Ordering>, RuntimeError> {
+        match (self, other) {
+            (Value::Int(value1), Value::Int(value2)) => Ok(Some(value1.cmp(value2))),
+            (Value::Int(value1), Value::Number(value2)) => Ok(compare_int_number(*value1, *value2)),
+            (Value::Number(value1), Value::Int(value2)) => Ok(compare_int_number(*value2, *value1).map(Ordering::reverse)),
+            (Value::Number(value1), Value::Number(value2)) => Ok(value1.partial_cmp(value2)),
+            (Value::String(value1), Value::String(value2)) => Ok(Some(value1.cmp(value2))),
+            (value1, value2) => Err(RuntimeError::type_error("compare", &[value1, value2]))
+        }
+    }
+}
+
+fn resolve_index(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+    match index {
+        Value::Int(value) => {
+            let resolved = if *value < 0 { *value + len as Int } else { *value };
+
+            if resolved < 0 || resolved >= len as Int {
+                let message = format!("index {} is out of range for length {}", value, len);
+                return Err(RuntimeError::new(RuntimeErrorKind::IndexError, message));
+            }
+
+            Ok(resolved as usize)
+        },
+        value => Err(RuntimeError::type_error("index with", &[value]))
+    }
+}
+
+fn slice_bounds(start: Option<&Value>, end: Option<&Value>, len: usize) -> Result<(usize, usize), RuntimeError> {
+    let bound = |value: Option<&Value>, default: usize| match value {
+        None => Ok(default),
+        Some(Value::Int(value)) => {
+            let resolved = if *value < 0 { *value + len as Int } else { *value };
+
+            Ok(resolved.clamp(0, len as Int) as usize)
+        },
+        Some(value) => Err(RuntimeError::type_error("slice with", &[value]))
+    };
+
+    let start = bound(start, 0)?;
+    let end = bound(end, len)?;
+
+    Ok((start, end.max(start)))
+}
+
+pub fn check_key(key: &Value) -> Result<(), RuntimeError> {
+    if key.is_hashable() {
+        return Ok(());
+    }
+
+    let message = format!("cannot use a {} as a map key", key.type_name());
+    Err(RuntimeError::new(RuntimeErrorKind::TypeError, message))
+}
+
+pub fn missing_key(key: &Value) -> RuntimeError {
+    RuntimeError::new(RuntimeErrorKind::KeyError, format!("key `{}` is not in the map", key))
+}
+
+// exact, so ints above 2^53 don't compare equal to a rounded number
+fn int_equals_number(int: Int, number: Number) -> bool {
+    number.fract() == 0.0 && number >= Int::MIN as Number && number < Int::MAX as Number && number as Int == int
+}
+
+// exact as well, so ordering agrees with `int_equals_number`
+fn compare_int_number(int: Int, number: Number) -> Option<Ordering> {
+    if number.is_nan() {
+        return None;
+    }
+
+    if number >= Int::MAX as Number {
+        return Some(Ordering::Less);
+    }
+
+    if number < Int::MIN as Number {
+        return Some(Ordering::Greater);
+    }
+
+    match int.cmp(&(number.trunc() as Int)) {
+        Ordering::Equal => (0.0).partial_cmp(&number.fract()),
+        ordering => Some(ordering)
+    }
+}
+
+// `equals` as `==`, so `1` and `1.0` are the same map key
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.equals(other)
+    }
+}
+
+// only holds for hashable values, which are the only ones used as keys
+impl Eq for Value { }
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
-            Value::Null => write!(f, "null"),
-            Value::Number(value) => write!(f, "{}", value),
-            Value::Bool(value) => write!(f, "{}", value),
-            Value::String(value) => write!(f, "{}", value),
-            Value::Function(_, _) => write!(f, "function"),
-            Value::NativeFunc(_) => write!(f, "function")
+            Value::Int(value) => value.hash(state),
+            Value::Number(value) if value.fract() == 0.0 && *value >= Int::MIN as Number && *value < Int::MAX as Number => {
+                (*value as Int).hash(state)
+            },
+            Value::Number(value) => value.to_bits().hash(state),
+            Value::Bool(value) => value.hash(state),
+            Value::String(value) => value.hash(state),
+            value => value.type_name().hash(state)
         }
     }
 }
 
+impl Debug for Value {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{}", self)
+    }
+}
+
+// how `print` and string interpolation show a value
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        display(self, f, &mut Vec::new())
+    }
+}
+
+// `ancestors` holds the lists and maps being printed further up,
+// one that contains itself prints as `[...]` or `{...}` the second time
+fn display(value: &Value, f: &mut Formatter, ancestors: &mut Vec<usize>) -> Result<(), Error> {
+    match value {
+        Value::Null => write!(f, "null"),
+        Value::Int(value) => write!(f, "{}", value),
+        Value::Number(value) => write!(f, "{}", value),
+        Value::Bool(value) => write!(f, "{}", value),
+        Value::String(value) => write!(f, "{}", value),
+        Value::List(items) if ancestors.contains(&address(items)) => write!(f, "[...]"),
+        Value::List(items) => {
+            ancestors.push(address(items));
+            write!(f, "[")?;
+
+            for (i, item) in items.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+
+                display(item, f, ancestors)?;
+            }
+
+            ancestors.pop();
+            write!(f, "]")
+        },
+        Value::Map(entries) if ancestors.contains(&address(entries)) => write!(f, "{{...}}"),
+        Value::Map(entries) => {
+            ancestors.push(address(entries));
+            write!(f, "{{")?;
+
+            for (i, (key, value)) in entries.borrow().entries().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+
+                display(key, f, ancestors)?;
+                write!(f, ": ")?;
+                display(value, f, ancestors)?;
+            }
+
+            ancestors.pop();
+            write!(f, "}}")
+        },
+        Value::Function(_, _, _) => write!(f, "function"),
+        Value::NativeFunc(_) => write!(f, "function")
+    }
+}
+
+// identifies a list or map while walking through nested ones
+fn address<T>(value: &Rc<RefCell<T>>) -> usize {
+    Rc::as_ptr(value) as *const u8 as usize
+}
+
 impl Neg for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeError>;
 
-    fn neg(self) -> Value {
+    fn neg(self) -> Result<Value, RuntimeError> {
         match self {
-            Value::Number(value) => Value::Number(-value),
-            _ => panic!("Cant negate items")
+            Value::Int(value) => value.checked_neg().map(Value::Int).ok_or_else(|| RuntimeError::overflow("negate", &[&Value::Int(value)])),
+            Value::Number(value) => Ok(Value::Number(-value)),
+            value => Err(RuntimeError::type_error("negate", &[&value]))
         }
     }
 }
 
+// ints stay ints unless they overflow, mixing an int with a number gives a number
+fn arithmetic(operation: &str, left: Value, right: Value, int_op: fn(Int, Int) -> Option<Int>, number_op: fn(Number, Number) -> Number) -> Result<Value, RuntimeError> {
+    match (left, right) {
+        (Value::Int(value1), Value::Int(value2)) => match int_op(value1, value2) {
+            Some(result) => Ok(Value::Int(result)),
+            None => Err(RuntimeError::overflow(operation, &[&Value::Int(value1), &Value::Int(value2)]))
+        },
+        (Value::Int(value1), Value::Number(value2)) => Ok(Value::Number(number_op(value1 as Number, value2))),
+        (Value::Number(value1), Value::Int(value2)) => Ok(Value::Number(number_op(value1, value2 as Number))),
+        (Value::Number(value1), Value::Number(value2)) => Ok(Value::Number(number_op(value1, value2))),
+        (value1, value2) => Err(RuntimeError::type_error(operation, &[&value1, &value2]))
+    }
+}
+
 impl Add for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeError>;
 
-    fn add(self, other: Value) -> Value {
+    fn add(self, other: Value) -> Result<Value, RuntimeError> {
         match (self, other) {
-            (Value::Number(value1), Value::Number(value2)) => Value::Number(value1 + value2),
-            (Value::String(value1), Value::String(value2)) => Value::String(value1 + &value2),
-            (Value::String(value1), Value::Number(value2)) => Value::String(value1 + &value2.to_string()),
-            (Value::Number(value1), Value::String(value2)) => Value::String(value1.to_string() + &value2),
-            (_, _) => panic!("Cant add items")
+            (Value::String(value1), Value::String(value2)) => Ok(Value::String(value1 + &value2)),
+            (Value::String(value1), Value::Int(value2)) => Ok(Value::String(value1 + &value2.to_string())),
+            (Value::Int(value1), Value::String(value2)) => Ok(Value::String(value1.to_string() + &value2)),
+            (Value::String(value1), Value::Number(value2)) => Ok(Value::String(value1 + &value2.to_string())),
+            (Value::Number(value1), Value::String(value2)) => Ok(Value::String(value1.to_string() + &value2)),
+            (value1, value2) => arithmetic("add", value1, value2, Int::checked_add, |a, b| a + b)
         }
     }
 }
 
 impl Sub for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeError>;
 
-    fn sub(self, other: Value) -> Value {
-        match (self, other) {
-            (Value::Number(value1), Value::Number(value2)) => Value::Number(value1 - value2),
-            (_, _) => panic!("Cant substract items")
-        }
+    fn sub(self, other: Value) -> Result<Value, RuntimeError> {
+        arithmetic("subtract", self, other, Int::checked_sub, |a, b| a - b)
     }
 }
 
 impl Mul for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeError>;
 
-    fn mul(self, other: Value) -> Value {
+    fn mul(self, other: Value) -> Result<Value, RuntimeError> {
+        arithmetic("multiply", self, other, Int::checked_mul, |a, b| a * b)
+    }
+}
+
+impl Div for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    // `/` always divides as numbers, so `1 / 2` is 0.5
+    fn div(self, other: Value) -> Result<Value, RuntimeError> {
         match (self, other) {
-            (Value::Number(value1), Value::Number(value2)) => Value::Number(value1 * value2),
-            (_, _) => panic!("Cant multiply items")
+            (Value::Int(value1), Value::Int(value2)) => Ok(Value::Number(value1 as Number / value2 as Number)),
+            (value1, value2) => arithmetic("divide", value1, value2, |_, _| None, |a, b| a / b)
         }
     }
 }
 
-impl Div for Value {
-    type Output = Value;
+impl Rem for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    // the result takes the sign of the divisor, so `-7 % 3` is 2
+    fn rem(self, other: Value) -> Result<Value, RuntimeError> {
+        int_division("take the remainder of", self, other, floor_mod, |a, b| {
+            let result = a % b;
+
+            if result != 0.0 && (result < 0.0) != (b < 0.0) { result + b } else { result }
+        })
+    }
+}
 
-    fn div(self, other: Value) -> Value {
+impl Value {
+    // `**`, a negative int exponent gives a number
+    pub fn pow(self, other: Value) -> Result<Value, RuntimeError> {
         match (self, other) {
-            (Value::Number(value1), Value::Number(value2)) => Value::Number(value1 / value2),
-            (_, _) => panic!("Cant divide items")
+            (Value::Int(value1), Value::Int(value2)) if value2 < 0 => Ok(Value::Number((value1 as Number).powf(value2 as Number))),
+            (value1, value2) => arithmetic("exponentiate", value1, value2, |a, b| {
+                if b > u32::MAX as Int { None } else { a.checked_pow(b as u32) }
+            }, Number::powf)
         }
     }
+
+    // `~/` rounds towards negative infinity, so `-7 ~/ 2` is -4
+    pub fn int_div(self, other: Value) -> Result<Value, RuntimeError> {
+        int_division("divide", self, other, floor_div, |a, b| (a / b).floor())
+    }
+}
+
+// like `arithmetic`, but an int divisor of zero is an error instead of an overflow
+fn int_division(operation: &str, left: Value, right: Value, int_op: fn(Int, Int) -> Option<Int>, number_op: fn(Number, Number) -> Number) -> Result<Value, RuntimeError> {
+    match (left, right) {
+        (Value::Int(value1), Value::Int(0)) => Err(RuntimeError::division_by_zero(operation, &[&Value::Int(value1), &Value::Int(0)])),
+        (value1, value2) => arithmetic(operation, value1, value2, int_op, number_op)
+    }
+}
+
+fn floor_div(a: Int, b: Int) -> Option<Int> {
+    let quotient = a.checked_div(b)?;
+
+    if a % b != 0 && (a < 0) != (b < 0) { Some(quotient - 1) } else { Some(quotient) }
+}
+
+fn floor_mod(a: Int, b: Int) -> Option<Int> {
+    let remainder = a.checked_rem(b)?;
+
+    if remainder != 0 && (remainder < 0) != (b < 0) { Some(remainder + b) } else { Some(remainder) }
+}
+
+// bitwise operators only work on ints
+fn bitwise(operation: &str, left: Value, right: Value, int_op: fn(Int, Int) -> Int) -> Result<Value, RuntimeError> {
+    match (left, right) {
+        (Value::Int(value1), Value::Int(value2)) => Ok(Value::Int(int_op(value1, value2))),
+        (value1, value2) => Err(RuntimeError::type_error(operation, &[&value1, &value2]))
+    }
+}
+
+// shifts keep the bit pattern, bits shifted past either end are dropped
+fn shift(operation: &str, left: Value, right: Value, int_op: fn(Int, u32) -> Int) -> Result<Value, RuntimeError> {
+    match (left, right) {
+        (Value::Int(value1), Value::Int(value2)) if (0..64).contains(&value2) => Ok(Value::Int(int_op(value1, value2 as u32))),
+        (Value::Int(_), Value::Int(value2)) => {
+            let message = format!("cannot shift by {} bits, the amount must be between 0 and 63", value2);
+            Err(RuntimeError::new(RuntimeErrorKind::OverflowError, message))
+        },
+        (value1, value2) => Err(RuntimeError::type_error(operation, &[&value1, &value2]))
+    }
+}
+
+impl Not for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    // `~` flips every bit of an int
+    fn not(self) -> Result<Value, RuntimeError> {
+        match self {
+            Value::Int(value) => Ok(Value::Int(!value)),
+            value => Err(RuntimeError::type_error("bitwise negate", &[&value]))
+        }
+    }
+}
+
+impl BitAnd for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn bitand(self, other: Value) -> Result<Value, RuntimeError> {
+        bitwise("bitwise and", self, other, |a, b| a & b)
+    }
+}
+
+impl BitOr for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn bitor(self, other: Value) -> Result<Value, RuntimeError> {
+        bitwise("bitwise or", self, other, |a, b| a | b)
+    }
+}
+
+impl BitXor for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn bitxor(self, other: Value) -> Result<Value, RuntimeError> {
+        bitwise("bitwise xor", self, other, |a, b| a ^ b)
+    }
+}
+
+impl Shl for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn shl(self, other: Value) -> Result<Value, RuntimeError> {
+        shift("shift", self, other, |a, b| a << b)
+    }
+}
+
+impl Shr for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn shr(self, other: Value) -> Result<Value, RuntimeError> {
+        shift("shift", self, other, |a, b| a >> b)
+    }
 }
@@ -0,0 +1,44 @@
+﻿use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// every script in example/golden must print exactly its .out file, a script with
+// a .err file must fail and print exactly that on stderr, a missing .out means no output
+#[test]
+fn golden_scripts() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = Path::new("example/golden");
+    let mut checked = 0;
+
+    for entry in fs::read_dir(root.join(dir)).expect("example/golden is missing") {
+        let name = entry.unwrap().file_name();
+        let script = dir.join(&name);
+
+        if script.extension().is_none_or(|extension| extension != "mlt") {
+            continue;
+        }
+
+        // relative, so the paths in diagnostics don't depend on where the crate lives
+        let output = Command::new(env!("CARGO_BIN_EXE_moonlight"))
+            .arg(&script)
+            .current_dir(root)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let expected = fs::read_to_string(root.join(script.with_extension("out"))).unwrap_or_default();
+
+        match fs::read_to_string(root.join(script.with_extension("err"))) {
+            Ok(expected_err) => {
+                assert!(!output.status.success(), "{} should have failed", script.display());
+                assert_eq!(stderr, expected_err, "{} reported something else", script.display());
+            },
+            Err(_) => assert!(output.status.success(), "{} failed:\n{}", script.display(), stderr)
+        }
+
+        assert_eq!(stdout, expected, "{} printed something else", script.display());
+        checked += 1;
+    }
+
+    assert!(checked > 0, "no scripts in {}", dir.display());
+}